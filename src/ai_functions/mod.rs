@@ -1,3 +1 @@
-pub mod ai_func_architect;
-pub mod ai_func_backend;
-pub mod ai_func_managing;
+pub mod prompts;
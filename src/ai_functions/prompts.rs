@@ -0,0 +1,271 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+pub const CONVERT_USER_INPUT_TO_GOAL: &str = "convert_user_input_to_goal";
+pub const PRINT_PROJECT_SCOPE: &str = "print_project_scope";
+pub const PRINT_SITE_URLS: &str = "print_site_urls";
+pub const PRINT_BACKEND_WEBSERVER_CODE: &str = "print_backend_webserver_code";
+pub const PRINT_IMPROVED_WEBSERVER_CODE: &str = "print_improved_webserver_code";
+pub const PRINT_FIXED_CODE: &str = "print_fixed_code";
+pub const PRINT_REST_API_ENDPOINTS: &str = "print_rest_api_endpoints";
+
+// The prompts of every AI function, compiled into the binary
+const DEFAULT_TEMPLATES: [(&str, &str); 7] = [
+    (
+        CONVERT_USER_INPUT_TO_GOAL,
+        include_str!("templates/convert_user_input_to_goal.md"),
+    ),
+    (
+        PRINT_PROJECT_SCOPE,
+        include_str!("templates/print_project_scope.md"),
+    ),
+    (
+        PRINT_SITE_URLS,
+        include_str!("templates/print_site_urls.md"),
+    ),
+    (
+        PRINT_BACKEND_WEBSERVER_CODE,
+        include_str!("templates/print_backend_webserver_code.md"),
+    ),
+    (
+        PRINT_IMPROVED_WEBSERVER_CODE,
+        include_str!("templates/print_improved_webserver_code.md"),
+    ),
+    (
+        PRINT_FIXED_CODE,
+        include_str!("templates/print_fixed_code.md"),
+    ),
+    (
+        PRINT_REST_API_ENDPOINTS,
+        include_str!("templates/print_rest_api_endpoints.md"),
+    ),
+];
+
+// Where to find prompt templates replacing the built-in ones
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct PromptConfig {
+    // Directory with <ai function>.md files, or .toml files with a table per AI function
+    pub directory: Option<String>,
+}
+
+// A table of a TOML prompt file
+#[derive(Debug, Deserialize)]
+struct TomlTemplate {
+    template: String,
+}
+
+// The prompt of an AI function with its variables filled in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prompt {
+    // The AI function, also used to pick the model and in the usage report
+    pub name: String,
+    pub text: String,
+}
+
+// Prompt templates per AI function, loaded once per run and shared by all agents
+#[derive(Debug, Clone)]
+pub struct PromptRegistry {
+    templates: BTreeMap<String, String>,
+}
+
+impl PromptRegistry {
+    // The built-in templates
+    pub fn defaults() -> Self {
+        let templates: BTreeMap<String, String> = DEFAULT_TEMPLATES
+            .iter()
+            .map(|(name, template)| (name.to_string(), template.to_string()))
+            .collect();
+        return Self { templates };
+    }
+
+    // The built-in templates, replaced by the ones in the configured directory
+    pub fn load(config: &PromptConfig) -> Result<Self, String> {
+        let mut registry: PromptRegistry = Self::defaults();
+        if let Some(directory) = &config.directory {
+            registry.load_directory(Path::new(directory))?;
+        }
+        return Ok(registry);
+    }
+
+    fn load_directory(&mut self, directory: &Path) -> Result<(), String> {
+        let entries: std::fs::ReadDir = std::fs::read_dir(directory).map_err(|e| {
+            format!(
+                "Failed to read prompt directory {}: {}",
+                directory.display(),
+                e
+            )
+        })?;
+        for entry in entries {
+            let path: std::path::PathBuf = entry.map_err(|e| e.to_string())?.path();
+            let contents = || {
+                std::fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read prompt {}: {}", path.display(), e))
+            };
+            match path.extension().and_then(|extension| extension.to_str()) {
+                Some("md") => {
+                    let name: &str = path
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .unwrap_or_default();
+                    self.replace(name, contents()?)?;
+                }
+                Some("toml") => {
+                    let templates: BTreeMap<String, TomlTemplate> = toml::from_str(&contents()?)
+                        .map_err(|e| {
+                            format!("Failed to parse prompts {}: {}", path.display(), e)
+                        })?;
+                    for (name, template) in templates {
+                        self.replace(&name, template.template)?;
+                    }
+                }
+                _ => (),
+            }
+        }
+        return Ok(());
+    }
+
+    // Only the prompts of existing AI functions can be replaced, a typo in a name is an error
+    fn replace(&mut self, name: &str, template: String) -> Result<(), String> {
+        let Some(current) = self.templates.get_mut(name) else {
+            return Err(format!("There is no AI function named {}", name));
+        };
+        *current = template;
+        return Ok(());
+    }
+
+    // The prompt of an AI function with the {variables} replaced by their values
+    pub fn render(&self, name: &str, variables: &[(&str, &str)]) -> Prompt {
+        let template: &String = self
+            .templates
+            .get(name)
+            .unwrap_or_else(|| panic!("There is no prompt template named {}", name));
+        return Prompt {
+            name: name.to_string(),
+            text: fill_variables(template, variables),
+        };
+    }
+}
+
+// Replace {name} by the value of the variable in a single pass, so values are never filled in
+// themselves. Other braces, e.g. in JSON examples or routes like /item/{id}, are left alone.
+fn fill_variables(template: &str, variables: &[(&str, &str)]) -> String {
+    let mut text: String = String::with_capacity(template.len());
+    let mut rest: &str = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value: Option<&str> = rest.find('}').and_then(|end| {
+            variables
+                .iter()
+                .find(|(name, _)| *name == &rest[1..end])
+                .map(|(_, value)| *value)
+        });
+        match value {
+            Some(value) => {
+                text.push_str(value);
+                rest = &rest[rest.find('}').unwrap() + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    return text;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_prompt() {
+        let registry: PromptRegistry = PromptRegistry::defaults();
+        let prompt: Prompt = registry.render(
+            PRINT_BACKEND_WEBSERVER_CODE,
+            &[
+                (
+                    "code_template",
+                    "async fn read_item() { {project_description} }",
+                ),
+                ("project_description", "a todo app"),
+            ],
+        );
+        assert_eq!(prompt.name, "print_backend_webserver_code");
+        assert!(
+            prompt
+                .text
+                .starts_with("INPUT: Takes in a PROJECT_DESCRIPTION")
+        );
+        assert!(prompt.text.ends_with(
+            "CODE_TEMPLATE: async fn read_item() { {project_description} }\nPROJECT_DESCRIPTION: a todo app\n"
+        ));
+
+        // Routes in the examples are no variables
+        let prompt: Prompt =
+            registry.render(PRINT_REST_API_ENDPOINTS, &[("code_input", "fn main() {}")]);
+        assert!(prompt.text.contains("\"route\": \"/item/{id}\""));
+        assert!(prompt.text.ends_with("CODE_INPUT: fn main() {}\n"));
+    }
+
+    #[test]
+    fn test_load_prompt_directory() {
+        // Per process, so a failed run leaves nothing behind for the next one
+        let directory: std::path::PathBuf =
+            std::env::temp_dir().join(format!("prompt_templates_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("print_fixed_code.md"),
+            "Fix {broken_code} using {error_bugs}",
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("architect.toml"),
+            "[print_site_urls]\ntemplate = \"List APIs for {project_description}\"\n",
+        )
+        .unwrap();
+        let config: PromptConfig = PromptConfig {
+            directory: Some(directory.to_str().unwrap().to_string()),
+        };
+
+        let registry: PromptRegistry = PromptRegistry::load(&config).unwrap();
+        assert_eq!(
+            registry
+                .render(
+                    PRINT_FIXED_CODE,
+                    &[("broken_code", "x"), ("error_bugs", "y")]
+                )
+                .text,
+            "Fix x using y"
+        );
+        assert_eq!(
+            registry
+                .render(PRINT_SITE_URLS, &[("project_description", "weather")])
+                .text,
+            "List APIs for weather"
+        );
+        assert_eq!(
+            registry.render(PRINT_PROJECT_SCOPE, &[]).text,
+            PromptRegistry::defaults()
+                .render(PRINT_PROJECT_SCOPE, &[])
+                .text
+        );
+
+        let typo_directory: std::path::PathBuf =
+            std::env::temp_dir().join(format!("prompt_templates_typo_{}", std::process::id()));
+        std::fs::create_dir_all(&typo_directory).unwrap();
+        std::fs::write(typo_directory.join("print_fixd_code.md"), "typo").unwrap();
+        let typo_config: PromptConfig = PromptConfig {
+            directory: Some(typo_directory.to_str().unwrap().to_string()),
+        };
+        assert_eq!(
+            PromptRegistry::load(&typo_config).unwrap_err(),
+            "There is no AI function named print_fixd_code"
+        );
+
+        std::fs::remove_dir_all(&directory).unwrap();
+        std::fs::remove_dir_all(&typo_directory).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+#![allow(unused)]
+use crate::apis::llm_error::LlmError;
+use crate::apis::llm_provider::{
+    CachedContent, CallContext, ChunkCallback, LlmProvider, LlmResponse,
+};
+use crate::apis::usage::{UsageLedger, UsageTotals};
+use crate::models::general::llm::Message;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::sync::Arc;
+
+// Limits for a single run, a missing limit means unlimited
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct Budget {
+    pub max_calls: Option<u32>,
+    pub max_tokens: Option<u32>,
+    // Estimated cost in US dollars, based on the pricing table
+    pub max_cost: Option<f64>,
+}
+
+impl Budget {
+    // Fails when the usage so far leaves no room for another call
+    pub fn check(&self, totals: &UsageTotals) -> Result<(), LlmError> {
+        if let Some(max_calls) = self.max_calls
+            && totals.calls >= max_calls
+        {
+            return Err(LlmError::BudgetExceeded(format!(
+                "{} of {} calls used",
+                totals.calls, max_calls
+            )));
+        }
+        if let Some(max_tokens) = self.max_tokens
+            && totals.total_tokens() >= max_tokens
+        {
+            return Err(LlmError::BudgetExceeded(format!(
+                "{} of {} tokens used",
+                totals.total_tokens(),
+                max_tokens
+            )));
+        }
+        if let Some(max_cost) = self.max_cost
+            && totals.cost >= max_cost
+        {
+            return Err(LlmError::BudgetExceeded(format!(
+                "${:.4} of ${:.4} spent",
+                totals.cost, max_cost
+            )));
+        }
+        return Ok(());
+    }
+}
+
+// Provider wrapper which refuses calls once the usage in the ledger reaches the budget
+#[derive(Debug)]
+pub struct BudgetProvider {
+    inner: Arc<dyn LlmProvider>,
+    ledger: Arc<UsageLedger>,
+    budget: Budget,
+}
+
+impl BudgetProvider {
+    pub fn new(inner: Arc<dyn LlmProvider>, ledger: Arc<UsageLedger>, budget: Budget) -> Self {
+        return Self {
+            inner,
+            ledger,
+            budget,
+        };
+    }
+}
+
+#[async_trait]
+impl LlmProvider for BudgetProvider {
+    async fn generate(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<LlmResponse, LlmError> {
+        self.budget.check(&self.ledger.totals())?;
+        return self.inner.generate(message, context).await;
+    }
+
+    async fn generate_stream(
+        &self,
+        message: &Message,
+        context: &CallContext,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<LlmResponse, LlmError> {
+        self.budget.check(&self.ledger.totals())?;
+        return self.inner.generate_stream(message, context, on_chunk).await;
+    }
+
+    // Counting tokens is free
+    async fn count_tokens(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<u32, LlmError> {
+        return self.inner.count_tokens(message, context).await;
+    }
+
+    async fn create_cached_content(
+        &self,
+        prefix: &Message,
+        context: &CallContext,
+    ) -> Result<Option<CachedContent>, LlmError> {
+        return self.inner.create_cached_content(prefix, context).await;
+    }
+
+    fn usage_ledger(&self) -> Option<Arc<UsageLedger>> {
+        return Some(Arc::clone(&self.ledger));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apis::usage::{MeteredProvider, default_pricing};
+    use crate::models::general::llm::{MessagePart, MessagePartText};
+    use crate::test_support::scripted_provider::ScriptedProvider;
+
+    fn message() -> Message {
+        return Message {
+            contents: vec![MessagePart::user("x".repeat(400))],
+            ..Default::default()
+        };
+    }
+
+    fn budgeted(inner: Arc<ScriptedProvider>, budget: Budget) -> BudgetProvider {
+        let ledger: Arc<UsageLedger> = Arc::new(UsageLedger::new(default_pricing()));
+        let metered: Arc<MeteredProvider> =
+            Arc::new(MeteredProvider::new(inner, Arc::clone(&ledger)));
+        return BudgetProvider::new(metered, ledger, budget);
+    }
+
+    #[tokio::test]
+    async fn test_budget_max_calls() {
+        let inner: Arc<ScriptedProvider> = Arc::new(ScriptedProvider::new(vec![
+            Ok("one".to_string()),
+            Ok("two".to_string()),
+            Ok("three".to_string()),
+        ]));
+        let provider: BudgetProvider = budgeted(
+            inner.clone(),
+            Budget {
+                max_calls: Some(2),
+                ..Default::default()
+            },
+        );
+        let context: CallContext = CallContext::default();
+
+        assert!(provider.generate(&message(), &context).await.is_ok());
+        assert!(provider.generate(&message(), &context).await.is_ok());
+        assert_eq!(
+            provider.generate(&message(), &context).await,
+            Err(LlmError::BudgetExceeded("2 of 2 calls used".to_string()))
+        );
+        assert_eq!(inner.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_budget_max_tokens() {
+        let inner: Arc<ScriptedProvider> = Arc::new(ScriptedProvider::new(vec![
+            Ok("one".to_string()),
+            Ok("two".to_string()),
+        ]));
+        let provider: BudgetProvider = budgeted(
+            inner.clone(),
+            Budget {
+                max_tokens: Some(100),
+                ..Default::default()
+            },
+        );
+        let context: CallContext = CallContext::default();
+
+        // The first call (101 tokens) is allowed, it only runs over the budget afterwards
+        assert!(provider.generate(&message(), &context).await.is_ok());
+        assert!(matches!(
+            provider.generate(&message(), &context).await,
+            Err(LlmError::BudgetExceeded(_))
+        ));
+        assert_eq!(inner.calls(), 1);
+    }
+}
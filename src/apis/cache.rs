@@ -0,0 +1,444 @@
+#![allow(unused)]
+use crate::apis::cassette::stable_hash;
+use crate::apis::llm_error::LlmError;
+use crate::apis::llm_provider::{
+    CachedContent, CallContext, ChunkCallback, LlmProvider, LlmResponse,
+};
+use crate::apis::usage::UsageLedger;
+use crate::models::general::llm::Message;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Keeps answers to deterministic (temperature 0) requests on disk, so rerunning the pipeline on the
+// same input does not pay for the same calls again
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct CacheConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_cache_directory")]
+    pub directory: String,
+    // Older answers are asked again
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+    // Skip the cached answers but store the new ones, e.g. after changing a prompt
+    #[serde(default)]
+    pub bypass: bool,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        return Self {
+            enabled: false,
+            directory: default_cache_directory(),
+            ttl_secs: default_ttl_secs(),
+            bypass: false,
+        };
+    }
+}
+
+fn default_cache_directory() -> String {
+    return ".autogemini/cache".to_string();
+}
+
+fn default_ttl_secs() -> u64 {
+    return 7 * 24 * 60 * 60;
+}
+
+// An answer on disk, with the moment it was given
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    created_at: u64,
+    response: LlmResponse,
+}
+
+// Provider wrapper answering repeated deterministic requests from the cache directory.
+// Cached answers are free: they never reach the budget or the usage ledger.
+#[derive(Debug)]
+pub struct CachedProvider {
+    inner: Arc<dyn LlmProvider>,
+    config: CacheConfig,
+    // The model which will answer, per AI function, so a model change is a cache miss
+    model: String,
+    models: BTreeMap<String, String>,
+    // Hash of the uploaded prefix per cached content name, the name changes with every upload
+    prefixes: Mutex<BTreeMap<String, String>>,
+}
+
+impl CachedProvider {
+    pub fn new(
+        inner: Arc<dyn LlmProvider>,
+        config: CacheConfig,
+        model: String,
+        models: BTreeMap<String, String>,
+    ) -> Self {
+        return Self {
+            inner,
+            config,
+            model,
+            models,
+            prefixes: Mutex::new(BTreeMap::new()),
+        };
+    }
+
+    // Cache file of the request, None when the request may not be cached
+    fn entry_path(&self, message: &Message, context: &CallContext) -> Option<PathBuf> {
+        let deterministic: bool = message
+            .generation_config
+            .as_ref()
+            .and_then(|config| config.temperature)
+            == Some(0.0);
+        if !deterministic {
+            return None;
+        }
+        let model: &str = self.models.get(&context.function).unwrap_or(&self.model);
+        // A prefix uploaded by another run has another name, so the key holds the prefix itself.
+        // Prefixes uploaded elsewhere are unknown and can not be cached.
+        let mut keyed: Message = message.clone();
+        let prefix: String = match keyed.cached_content.take() {
+            Some(name) => self.prefixes.lock().unwrap().get(&name)?.clone(),
+            None => String::new(),
+        };
+        let serialized: String = serde_json::to_string(&keyed).ok()?;
+        let key: String = stable_hash(&[model, &prefix, &serialized]);
+        return Some(PathBuf::from(&self.config.directory).join(format!("{}.json", key)));
+    }
+
+    fn lookup(&self, path: &PathBuf) -> Option<LlmResponse> {
+        if self.config.bypass {
+            return None;
+        }
+        let entry: CacheEntry = serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()?;
+        if now_secs().saturating_sub(entry.created_at) >= self.config.ttl_secs {
+            return None;
+        }
+        return Some(entry.response);
+    }
+
+    // A cache which can not be written only costs the call next time
+    fn store(&self, path: &PathBuf, response: &LlmResponse) {
+        let entry: CacheEntry = CacheEntry {
+            created_at: now_secs(),
+            response: response.clone(),
+        };
+        if let Ok(contents) = serde_json::to_string_pretty(&entry) {
+            let _ = std::fs::create_dir_all(&self.config.directory);
+            let _ = std::fs::write(path, contents);
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+}
+
+#[async_trait]
+impl LlmProvider for CachedProvider {
+    async fn generate(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<LlmResponse, LlmError> {
+        let Some(path) = self.entry_path(message, context) else {
+            return self.inner.generate(message, context).await;
+        };
+        if let Some(response) = self.lookup(&path) {
+            return Ok(response);
+        }
+        let response: LlmResponse = self.inner.generate(message, context).await?;
+        self.store(&path, &response);
+        return Ok(response);
+    }
+
+    async fn generate_stream(
+        &self,
+        message: &Message,
+        context: &CallContext,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<LlmResponse, LlmError> {
+        let Some(path) = self.entry_path(message, context) else {
+            return self.inner.generate_stream(message, context, on_chunk).await;
+        };
+        if let Some(response) = self.lookup(&path) {
+            on_chunk(&response.text);
+            return Ok(response);
+        }
+        let response: LlmResponse = self
+            .inner
+            .generate_stream(message, context, on_chunk)
+            .await?;
+        self.store(&path, &response);
+        return Ok(response);
+    }
+
+    async fn count_tokens(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<u32, LlmError> {
+        return self.inner.count_tokens(message, context).await;
+    }
+
+    async fn create_cached_content(
+        &self,
+        prefix: &Message,
+        context: &CallContext,
+    ) -> Result<Option<CachedContent>, LlmError> {
+        let cached: Option<CachedContent> =
+            self.inner.create_cached_content(prefix, context).await?;
+        if let Some(cached) = &cached {
+            let serialized: String = serde_json::to_string(prefix)?;
+            self.prefixes
+                .lock()
+                .unwrap()
+                .insert(cached.name.clone(), stable_hash(&[&serialized]));
+        }
+        return Ok(cached);
+    }
+
+    fn usage_ledger(&self) -> Option<Arc<UsageLedger>> {
+        return self.inner.usage_ledger();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::general::llm::{GenerationConfig, MessagePart};
+    use crate::test_support::scripted_provider::ScriptedProvider;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    // Answers from the script and gives every uploaded prefix a new name, like Gemini
+    #[derive(Debug)]
+    struct Uploads {
+        scripted: ScriptedProvider,
+        uploads: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl LlmProvider for Uploads {
+        async fn generate(
+            &self,
+            message: &Message,
+            context: &CallContext,
+        ) -> Result<LlmResponse, LlmError> {
+            return self.scripted.generate(message, context).await;
+        }
+
+        async fn count_tokens(
+            &self,
+            message: &Message,
+            context: &CallContext,
+        ) -> Result<u32, LlmError> {
+            return Ok(0);
+        }
+
+        async fn create_cached_content(
+            &self,
+            prefix: &Message,
+            context: &CallContext,
+        ) -> Result<Option<CachedContent>, LlmError> {
+            let upload: usize = self.uploads.fetch_add(1, Ordering::SeqCst);
+            return Ok(Some(CachedContent {
+                name: format!("cachedContents/upload-{}", upload),
+                expires_at: Instant::now() + Duration::from_secs(300),
+            }));
+        }
+    }
+
+    fn message(temperature: f64) -> Message {
+        return Message {
+            contents: vec![MessagePart::user("Build me a todo app".to_string())],
+            generation_config: Some(GenerationConfig {
+                temperature: Some(temperature),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+    }
+
+    fn cached(inner: &Arc<ScriptedProvider>, name: &str, config: CacheConfig) -> CachedProvider {
+        let directory: PathBuf = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&directory);
+        let config: CacheConfig = CacheConfig {
+            enabled: true,
+            directory: directory.to_str().unwrap().to_string(),
+            ..config
+        };
+        return CachedProvider::new(
+            Arc::clone(inner) as Arc<dyn LlmProvider>,
+            config,
+            "gemini-2.0-flash".to_string(),
+            BTreeMap::from([("print_fixed_code".to_string(), "gemini-2.5-pro".to_string())]),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cached_provider_serves_deterministic_calls() {
+        let inner: Arc<ScriptedProvider> = Arc::new(ScriptedProvider::new(vec![
+            Ok("a todo app".to_string()),
+            Ok("a todo app with users".to_string()),
+            Ok("fn main() {}".to_string()),
+        ]));
+        let cache: CachedProvider = cached(&inner, "cached_provider", CacheConfig::default());
+        let goal: CallContext = CallContext::new("Project Manager", "convert_user_input_to_goal");
+
+        let first: LlmResponse = cache.generate(&message(0.0), &goal).await.unwrap();
+        let second: LlmResponse = cache.generate(&message(0.0), &goal).await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(inner.calls(), 1);
+
+        // Creative calls and calls answered by another model are not served from the cache
+        cache.generate(&message(0.7), &goal).await.unwrap();
+        let fix: CallContext = CallContext::new("Backend developer", "print_fixed_code");
+        let mut chunks: Vec<String> = vec![];
+        cache
+            .generate_stream(&message(0.0), &fix, &mut |chunk| {
+                chunks.push(chunk.to_string())
+            })
+            .await
+            .unwrap();
+        assert_eq!(inner.calls(), 3);
+        let streamed: LlmResponse = cache
+            .generate_stream(&message(0.0), &fix, &mut |chunk| {
+                chunks.push(chunk.to_string())
+            })
+            .await
+            .unwrap();
+        assert_eq!(streamed.text, "fn main() {}");
+        assert_eq!(chunks, vec!["fn main() {}", "fn main() {}"]);
+        assert_eq!(inner.calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_cached_provider_bypass_and_ttl() {
+        let inner: Arc<ScriptedProvider> = Arc::new(ScriptedProvider::new(vec![
+            Ok("old goal".to_string()),
+            Ok("new goal".to_string()),
+        ]));
+        let goal: CallContext = CallContext::new("Project Manager", "convert_user_input_to_goal");
+
+        // Bypassing asks again and replaces the cached answer
+        let cache: CachedProvider = cached(
+            &inner,
+            "cached_provider_bypass",
+            CacheConfig {
+                bypass: true,
+                ..Default::default()
+            },
+        );
+        cache.generate(&message(0.0), &goal).await.unwrap();
+        let response: LlmResponse = cache.generate(&message(0.0), &goal).await.unwrap();
+        assert_eq!(response.text, "new goal");
+        assert_eq!(inner.calls(), 2);
+
+        // Expired answers are asked again
+        let inner: Arc<ScriptedProvider> = Arc::new(ScriptedProvider::new(vec![
+            Ok("old goal".to_string()),
+            Ok("new goal".to_string()),
+        ]));
+        let cache: CachedProvider = cached(
+            &inner,
+            "cached_provider_ttl",
+            CacheConfig {
+                ttl_secs: 0,
+                ..Default::default()
+            },
+        );
+        cache.generate(&message(0.0), &goal).await.unwrap();
+        let response: LlmResponse = cache.generate(&message(0.0), &goal).await.unwrap();
+        assert_eq!(response.text, "new goal");
+    }
+
+    #[tokio::test]
+    async fn test_cached_provider_keys_on_the_uploaded_prefix() {
+        let inner: Arc<Uploads> = Arc::new(Uploads {
+            scripted: ScriptedProvider::new(vec![
+                Ok("fn main() {}".to_string()),
+                Ok("fn main() { todo!() }".to_string()),
+                Ok("fn other() {}".to_string()),
+                Ok("fn other() {}".to_string()),
+            ]),
+            uploads: AtomicUsize::new(0),
+        });
+        let directory: PathBuf = std::env::temp_dir().join("cached_provider_prefix");
+        let _ = std::fs::remove_dir_all(&directory);
+        let run = || {
+            CachedProvider::new(
+                Arc::clone(&inner) as Arc<dyn LlmProvider>,
+                CacheConfig {
+                    enabled: true,
+                    directory: directory.to_str().unwrap().to_string(),
+                    ..Default::default()
+                },
+                "gemini-2.0-flash".to_string(),
+                BTreeMap::new(),
+            )
+        };
+        let context: CallContext = CallContext::new("Backend developer", "print_fixed_code");
+        let prefix = |text: &str| Message {
+            contents: vec![MessagePart::user(text.to_string())],
+            ..Default::default()
+        };
+        let with_prefix = |name: String| Message {
+            cached_content: Some(name),
+            ..message(0.0)
+        };
+
+        let first_run: CachedProvider = run();
+        let uploaded: CachedContent = first_run
+            .create_cached_content(&prefix("PROJECT DESCRIPTION: todo app"), &context)
+            .await
+            .unwrap()
+            .unwrap();
+        first_run
+            .generate(&with_prefix(uploaded.name), &context)
+            .await
+            .unwrap();
+
+        // The next run uploads the same prefix under a new name and gets the stored answer
+        let second_run: CachedProvider = run();
+        let uploaded: CachedContent = second_run
+            .create_cached_content(&prefix("PROJECT DESCRIPTION: todo app"), &context)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(uploaded.name, "cachedContents/upload-1");
+        let response: LlmResponse = second_run
+            .generate(&with_prefix(uploaded.name), &context)
+            .await
+            .unwrap();
+        assert_eq!(response.text, "fn main() {}");
+        assert_eq!(inner.scripted.calls(), 1);
+
+        // Another prefix is another question
+        let uploaded: CachedContent = second_run
+            .create_cached_content(&prefix("PROJECT DESCRIPTION: weather app"), &context)
+            .await
+            .unwrap()
+            .unwrap();
+        let response: LlmResponse = second_run
+            .generate(&with_prefix(uploaded.name), &context)
+            .await
+            .unwrap();
+        assert_eq!(response.text, "fn main() { todo!() }");
+
+        // A prefix this run did not upload is unknown, so its answers are never cached
+        for _ in 0..2 {
+            second_run
+                .generate(
+                    &with_prefix("cachedContents/elsewhere".to_string()),
+                    &context,
+                )
+                .await
+                .unwrap();
+        }
+        assert_eq!(inner.scripted.calls(), 4);
+    }
+}
@@ -0,0 +1,183 @@
+#![allow(unused)]
+
+use crate::apis::call_openai::finish_reason_from_chat;
+use crate::apis::llm_error::{LlmError, check_response};
+use crate::apis::llm_provider::{
+    CallContext, LlmProvider, LlmResponse, TokenUsage, estimate_tokens, estimate_usage,
+};
+use crate::helpers::config::OllamaConfig;
+use crate::models::general::llm::{GenerationConfig, Message, MessagePart, MessagePartText};
+use crate::models::general::ollama::{
+    OllamaChatRequest, OllamaChatResponse, OllamaMessage, OllamaOptions,
+};
+use async_trait::async_trait;
+use std::time::Duration;
+
+// Local Ollama server through its native /api/chat endpoint, for fully offline runs
+#[derive(Debug, Clone)]
+pub struct OllamaProvider {
+    // Keeps the connections open between calls
+    http: reqwest::Client,
+    base_url: String,
+    model: String,
+}
+
+impl OllamaProvider {
+    pub fn new(config: &OllamaConfig) -> Result<Self, LlmError> {
+        let http: reqwest::Client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .timeout(Duration::from_secs(config.request_timeout_secs))
+            .build()?;
+        return Ok(Self {
+            http,
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            model: config.model.clone(),
+        });
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+    async fn generate(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<LlmResponse, LlmError> {
+        let request: OllamaChatRequest = ollama_request_from_message(message, &self.model);
+        let response: reqwest::Response = self
+            .http
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&request)
+            .send()
+            .await?;
+        let response: OllamaChatResponse = check_response(response).await?.json().await?;
+
+        let estimate: TokenUsage = estimate_usage(message, &response.message.content);
+        return Ok(LlmResponse {
+            usage: TokenUsage {
+                prompt_tokens: response.prompt_eval_count.unwrap_or(estimate.prompt_tokens),
+                completion_tokens: response.eval_count.unwrap_or(estimate.completion_tokens),
+            },
+            model: self.model.clone(),
+            text: response.message.content,
+            finish_reason: finish_reason_from_chat(response.done_reason.as_deref()),
+            function_calls: vec![],
+        });
+    }
+
+    // Ollama has no token counting endpoint
+    async fn count_tokens(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<u32, LlmError> {
+        return Ok(estimate_tokens(message));
+    }
+}
+
+// Translate a Gemini style message into a non streaming Ollama chat request
+pub fn ollama_request_from_message(message: &Message, model: &str) -> OllamaChatRequest {
+    let mut messages: Vec<OllamaMessage> = vec![];
+    if let Some(instruction) = &message.system_instruction {
+        messages.push(OllamaMessage {
+            role: "system".to_string(),
+            content: instruction.joined_text(),
+        });
+    }
+    // Only the text goes along, attachments and tool calls are Gemini features
+    messages.extend(message.contents.iter().map(|content| OllamaMessage {
+        role: content.chat_role().to_string(),
+        content: content.joined_text(),
+    }));
+
+    let options: Option<OllamaOptions> =
+        message
+            .generation_config
+            .as_ref()
+            .map(|config: &GenerationConfig| OllamaOptions {
+                temperature: config.temperature,
+                num_predict: config.max_output_tokens,
+            });
+
+    // Ollama only offers a generic JSON mode
+    let format: Option<String> = message
+        .generation_config
+        .as_ref()
+        .and_then(|config| config.response_mime_type.as_deref())
+        .filter(|mime_type| *mime_type == "application/json")
+        .map(|_| "json".to_string());
+
+    return OllamaChatRequest {
+        model: model.to_string(),
+        messages,
+        stream: false,
+        format,
+        options,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::stub_server::{StubResponse, StubServer};
+
+    #[tokio::test]
+    async fn test_ollama_against_stub_server() {
+        let server: StubServer = StubServer::start(|_request| {
+            StubResponse::json(
+                200,
+                serde_json::json!({
+                    "model": "llama3.2",
+                    "message": { "role": "assistant", "content": "build a website that ..." },
+                    "done": true,
+                    "done_reason": "stop",
+                    "prompt_eval_count": 12,
+                    "eval_count": 6
+                }),
+            )
+        })
+        .await;
+
+        let provider: OllamaProvider = OllamaProvider::new(&OllamaConfig {
+            base_url: server.base_url.clone(),
+            model: "llama3.2".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let message: Message = Message {
+            contents: vec![MessagePart::user("Build me a todo app".to_string())],
+            generation_config: Some(GenerationConfig {
+                temperature: Some(0.0),
+                max_output_tokens: Some(256),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let response: LlmResponse = provider
+            .generate(&message, &CallContext::default())
+            .await
+            .unwrap();
+        assert_eq!(response.text, "build a website that ...");
+        assert_eq!(
+            response.usage,
+            TokenUsage {
+                prompt_tokens: 12,
+                completion_tokens: 6
+            }
+        );
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/api/chat");
+        assert_eq!(
+            requests[0].json(),
+            serde_json::json!({
+                "model": "llama3.2",
+                "messages": [{ "role": "user", "content": "Build me a todo app" }],
+                "stream": false,
+                "options": { "temperature": 0.0, "num_predict": 256 }
+            })
+        );
+    }
+}
@@ -0,0 +1,305 @@
+#![allow(unused)]
+
+use crate::apis::llm_error::{LlmError, check_response};
+use crate::apis::llm_provider::{
+    CallContext, FinishReason, LlmProvider, LlmResponse, TokenUsage, estimate_tokens,
+    estimate_usage,
+};
+use crate::helpers::config::OpenAiConfig;
+use crate::helpers::secret::ApiKey;
+use crate::models::general::llm::{GenerationConfig, Message, MessagePart, MessagePartText};
+use crate::models::general::openai::{
+    ChatChoice, ChatCompletionRequest, ChatCompletionResponse, ChatMessage, ChatUsage,
+};
+use async_trait::async_trait;
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderValue};
+use std::time::Duration;
+
+// Any server speaking the OpenAI /v1/chat/completions protocol (vLLM, llama.cpp, ...)
+#[derive(Debug, Clone)]
+pub struct OpenAiProvider {
+    // Keeps the connections open between calls
+    http: reqwest::Client,
+    base_url: String,
+    model: String,
+}
+
+impl OpenAiProvider {
+    pub fn new(config: &OpenAiConfig) -> Result<Self, LlmError> {
+        // Create headers. Local servers usually do not need an API key.
+        let mut openai_headers: HeaderMap = HeaderMap::new();
+        openai_headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+        if let Some(api_key) = &config.api_key {
+            let mut authorization: HeaderValue =
+                HeaderValue::from_str(&format!("Bearer {}", api_key.expose()))
+                    .map_err(|_| LlmError::MissingCredentials("OPENAI_API_KEY".to_string()))?;
+            // Keeps the key out of debug prints of the request
+            authorization.set_sensitive(true);
+            openai_headers.insert(AUTHORIZATION, authorization);
+        }
+
+        let http: reqwest::Client = reqwest::Client::builder()
+            .default_headers(openai_headers)
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .timeout(Duration::from_secs(config.request_timeout_secs))
+            .build()?;
+        return Ok(Self {
+            http,
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            model: config.model.clone(),
+        });
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+    async fn generate(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<LlmResponse, LlmError> {
+        let request: ChatCompletionRequest = chat_request_from_message(message, &self.model);
+        let response: reqwest::Response = self
+            .http
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(&request)
+            .send()
+            .await?;
+        let response: ChatCompletionResponse = check_response(response).await?.json().await?;
+
+        let usage: Option<ChatUsage> = response.usage.clone();
+        let finish_reason: Option<String> = response
+            .choices
+            .first()
+            .and_then(|choice| choice.finish_reason.clone());
+        if finish_reason.as_deref() == Some("content_filter") {
+            return Err(LlmError::Blocked {
+                reason: "content_filter".to_string(),
+            });
+        }
+        let text: String = text_from_chat_response(response)?;
+        let estimate: TokenUsage = estimate_usage(message, &text);
+        return Ok(LlmResponse {
+            usage: TokenUsage {
+                prompt_tokens: usage
+                    .as_ref()
+                    .and_then(|usage| usage.prompt_tokens)
+                    .unwrap_or(estimate.prompt_tokens),
+                completion_tokens: usage
+                    .as_ref()
+                    .and_then(|usage| usage.completion_tokens)
+                    .unwrap_or(estimate.completion_tokens),
+            },
+            model: self.model.clone(),
+            text,
+            finish_reason: finish_reason_from_chat(finish_reason.as_deref()),
+            function_calls: vec![],
+        });
+    }
+
+    // The chat completions protocol has no token counting endpoint
+    async fn count_tokens(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<u32, LlmError> {
+        return Ok(estimate_tokens(message));
+    }
+}
+
+// Translate a Gemini style message into a chat completions request
+pub fn chat_request_from_message(message: &Message, model: &str) -> ChatCompletionRequest {
+    let mut messages: Vec<ChatMessage> = vec![];
+    if let Some(instruction) = &message.system_instruction {
+        messages.push(ChatMessage {
+            role: "system".to_string(),
+            content: instruction.joined_text(),
+        });
+    }
+    // Only the text goes along, attachments and tool calls are Gemini features
+    messages.extend(message.contents.iter().map(|content| ChatMessage {
+        role: content.chat_role().to_string(),
+        content: content.joined_text(),
+    }));
+
+    let generation_config: Option<&GenerationConfig> = message.generation_config.as_ref();
+    return ChatCompletionRequest {
+        model: model.to_string(),
+        messages,
+        temperature: generation_config.and_then(|config| config.temperature),
+        max_tokens: generation_config.and_then(|config| config.max_output_tokens),
+        // JSON mode, the Gemini schema dialect is not understood by chat completion servers
+        response_format: generation_config
+            .and_then(|config| config.response_mime_type.as_deref())
+            .filter(|mime_type| *mime_type == "application/json")
+            .map(|_| serde_json::json!({ "type": "json_object" })),
+    };
+}
+
+// Why a chat completion stopped ("stop", "length", ...), Ollama uses the same words
+pub fn finish_reason_from_chat(finish_reason: Option<&str>) -> FinishReason {
+    return match finish_reason {
+        None | Some("stop") => FinishReason::Stop,
+        Some("length") => FinishReason::MaxTokens,
+        Some(reason) => FinishReason::Other(reason.to_string()),
+    };
+}
+
+// The text of the first choice, an answer without choices is an error the retry policy handles
+pub fn text_from_chat_response(response: ChatCompletionResponse) -> Result<String, LlmError> {
+    let choice: ChatChoice = response
+        .choices
+        .into_iter()
+        .next()
+        .ok_or(LlmError::EmptyCandidates)?;
+    return Ok(choice.message.content.unwrap_or_default());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::stub_server::{StubResponse, StubServer};
+
+    #[test]
+    fn test_chat_request_from_message() {
+        let message: Message = Message {
+            contents: vec![
+                MessagePart {
+                    role: Some("user".to_string()),
+                    parts: vec![
+                        MessagePartText {
+                            text: "Hello".to_string(),
+                            ..Default::default()
+                        },
+                        MessagePartText {
+                            text: "World".to_string(),
+                            ..Default::default()
+                        },
+                    ],
+                },
+                MessagePart::model("Hi".to_string()),
+            ],
+            system_instruction: Some(MessagePart::text("Be brief".to_string())),
+            generation_config: Some(GenerationConfig {
+                temperature: Some(0.2),
+                max_output_tokens: Some(100),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let request: ChatCompletionRequest = chat_request_from_message(&message, "llama3");
+        let body: serde_json::Value = serde_json::to_value(&request).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "model": "llama3",
+                "messages": [
+                    { "role": "system", "content": "Be brief" },
+                    { "role": "user", "content": "Hello\nWorld" },
+                    { "role": "assistant", "content": "Hi" }
+                ],
+                "temperature": 0.2,
+                "max_tokens": 100
+            })
+        );
+    }
+
+    #[test]
+    fn test_text_from_chat_response() {
+        let response: ChatCompletionResponse = serde_json::from_str(
+            r#"{
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "model": "llama3",
+                "choices": [
+                    { "index": 0, "message": { "role": "assistant", "content": "build a website that ..." }, "finish_reason": "stop" }
+                ],
+                "usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            text_from_chat_response(response).unwrap(),
+            "build a website that ..."
+        );
+        let response: ChatCompletionResponse = serde_json::from_str(
+            r#"{ "id": "chatcmpl-2", "object": "chat.completion", "model": "llama3", "choices": [] }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            text_from_chat_response(response),
+            Err(LlmError::EmptyCandidates)
+        ));
+        assert_eq!(finish_reason_from_chat(Some("stop")), FinishReason::Stop);
+        assert_eq!(
+            finish_reason_from_chat(Some("length")),
+            FinishReason::MaxTokens
+        );
+    }
+
+    #[tokio::test]
+    async fn test_openai_against_stub_server() {
+        let server: StubServer = StubServer::start(|_request| {
+            StubResponse::json(
+                200,
+                serde_json::json!({
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion",
+                    "model": "llama3",
+                    "choices": [
+                        { "index": 0, "message": { "role": "assistant", "content": "{\"is_crud_required\":true}" }, "finish_reason": "length" }
+                    ],
+                    "usage": { "prompt_tokens": 14, "completion_tokens": 7, "total_tokens": 21 }
+                }),
+            )
+        })
+        .await;
+
+        let provider: OpenAiProvider = OpenAiProvider::new(&OpenAiConfig {
+            base_url: format!("{}/v1/", server.base_url),
+            model: "llama3".to_string(),
+            api_key: Some(ApiKey::new("sk-test")),
+            ..Default::default()
+        })
+        .unwrap();
+        let message: Message = Message {
+            contents: vec![MessagePart::user("Scope a todo app".to_string())],
+            generation_config: Some(GenerationConfig {
+                max_output_tokens: Some(64),
+                response_mime_type: Some("application/json".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let response: LlmResponse = provider
+            .generate(&message, &CallContext::default())
+            .await
+            .unwrap();
+        assert_eq!(response.text, "{\"is_crud_required\":true}");
+        assert_eq!(response.model, "llama3");
+        assert_eq!(response.finish_reason, FinishReason::MaxTokens);
+        assert_eq!(
+            response.usage,
+            TokenUsage {
+                prompt_tokens: 14,
+                completion_tokens: 7
+            }
+        );
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/v1/chat/completions");
+        assert_eq!(requests[0].header("authorization"), Some("Bearer sk-test"));
+        assert_eq!(
+            requests[0].json(),
+            serde_json::json!({
+                "model": "llama3",
+                "messages": [{ "role": "user", "content": "Scope a todo app" }],
+                "max_tokens": 64,
+                "response_format": { "type": "json_object" }
+            })
+        );
+    }
+}
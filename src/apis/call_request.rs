@@ -1,80 +1,747 @@
 #![allow(unused)]
 
+use crate::apis::llm_error::{LlmError, check_response};
+use crate::apis::llm_provider::{
+    CachedContent, CallContext, ChunkCallback, FinishReason, LlmProvider, LlmResponse, TokenUsage,
+    estimate_tokens, estimate_usage,
+};
+use crate::helpers::config::GeminiConfig;
+use crate::helpers::secret::ApiKey;
 use crate::models::general::llm::{
-    GeminiResponse, GenerationConfig, Message, MessagePart, MessagePartText,
+    CachedContentResponse, CountTokensResponse, FunctionCall, GeminiResponse, GenerationConfig,
+    Message, MessagePart, MessagePartText, ResponseContent, SafetyRating, UsageMetadata,
 };
-use dotenv::dotenv;
+use async_trait::async_trait;
 use reqwest::header::{HeaderMap, HeaderValue};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// Finish reasons which mean the filters stopped the answer, partial text is not usable either
+const BLOCKED_FINISH_REASONS: [&str; 5] = [
+    "SAFETY",
+    "PROHIBITED_CONTENT",
+    "BLOCKLIST",
+    "SPII",
+    "RECITATION",
+];
+// Gemini refuses to cache smaller contexts. Only the 2.5 Flash models accept 1024 tokens.
+const MIN_CACHED_CONTENT_TOKENS: u32 = 4096;
+const MIN_CACHED_CONTENT_TOKENS_FLASH_2_5: u32 = 1024;
+
+// Connection to Gemini, built once per run from the config and shared by all agents
+#[derive(Debug, Clone)]
+pub struct GeminiClient {
+    // Keeps the connections open between calls
+    http: reqwest::Client,
+    base_url: String,
+    model: String,
+    // AI function name -> model, overriding the default model
+    models: BTreeMap<String, String>,
+    cached_content_ttl_secs: u64,
+    api_key: Option<ApiKey>,
+}
+
+impl GeminiClient {
+    pub fn new(config: &GeminiConfig) -> Result<Self, LlmError> {
+        // Create headers, every request sends JSON
+        let mut gemini_headers: HeaderMap = HeaderMap::new();
+        gemini_headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+
+        let mut builder: reqwest::ClientBuilder = reqwest::Client::builder()
+            .default_headers(gemini_headers)
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .timeout(Duration::from_secs(config.request_timeout_secs));
+        if let Some(proxy) = &config.proxy {
+            let proxy: reqwest::Proxy = reqwest::Proxy::all(proxy)
+                .map_err(|e| LlmError::Network(format!("Invalid Gemini proxy: {}", e)))?;
+            builder = builder.proxy(proxy);
+        }
+
+        return Ok(Self {
+            http: builder.build()?,
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            model: config.model.clone(),
+            models: config.models.clone(),
+            cached_content_ttl_secs: config.cached_content_ttl_secs,
+            api_key: config.api_key.clone(),
+        });
+    }
+
+    // The model configured for an AI function, or the default model
+    pub fn model_for(&self, function: &str) -> &str {
+        return self.models.get(function).unwrap_or(&self.model);
+    }
+
+    // Start a request to a Gemini method with an optional query (e.g. "?alt=sse")
+    fn post(
+        &self,
+        model: &str,
+        method: &str,
+        query: &str,
+    ) -> Result<reqwest::RequestBuilder, LlmError> {
+        return self.post_to(&format!("models/{}:{}{}", model, method, query));
+    }
+
+    // Start a request to a path of the API. The key goes in a header, so it never ends up in a
+    // logged url.
+    fn post_to(&self, path: &str) -> Result<reqwest::RequestBuilder, LlmError> {
+        let gemini_api_key: &ApiKey = self
+            .api_key
+            .as_ref()
+            .ok_or_else(|| LlmError::MissingCredentials("GEMINI_API_KEY".to_string()))?;
+        let mut key_header: HeaderValue = HeaderValue::from_str(gemini_api_key.expose())
+            .map_err(|_| LlmError::MissingCredentials("GEMINI_API_KEY".to_string()))?;
+        key_header.set_sensitive(true);
+
+        return Ok(self
+            .http
+            .post(format!("{}/{}", self.base_url, path))
+            .header("x-goog-api-key", key_header));
+    }
+
+    // Make sure an error does not repeat the key, e.g. when the server echoes the request
+    fn redact(&self, error: LlmError) -> LlmError {
+        return match &self.api_key {
+            Some(api_key) => error.redacted(api_key),
+            None => error,
+        };
+    }
+}
 
-use std::env;
+// Gemini as a pluggable provider for the agents
+#[async_trait]
+impl LlmProvider for GeminiClient {
+    async fn generate(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<LlmResponse, LlmError> {
+        return call_gemini(self, self.model_for(&context.function), message)
+            .await
+            .map_err(|error| self.redact(error));
+    }
+
+    async fn generate_stream(
+        &self,
+        message: &Message,
+        context: &CallContext,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<LlmResponse, LlmError> {
+        return call_gemini_stream(self, self.model_for(&context.function), message, on_chunk)
+            .await
+            .map_err(|error| self.redact(error));
+    }
+
+    async fn count_tokens(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<u32, LlmError> {
+        return call_gemini_count_tokens(self, self.model_for(&context.function), message)
+            .await
+            .map_err(|error| self.redact(error));
+    }
+
+    async fn create_cached_content(
+        &self,
+        prefix: &Message,
+        context: &CallContext,
+    ) -> Result<Option<CachedContent>, LlmError> {
+        // The real count, an estimate may pass a prefix the API then refuses
+        let model: &str = self.model_for(&context.function);
+        let tokens: u32 = call_gemini_count_tokens(self, model, prefix)
+            .await
+            .map_err(|error| self.redact(error))?;
+        if tokens < min_cached_content_tokens(model) {
+            return Ok(None);
+        }
+
+        // Counted from before the upload, so the cache never outlives the expiry we keep
+        let expires_at: Instant =
+            Instant::now() + Duration::from_secs(self.cached_content_ttl_secs);
+        let name: String =
+            call_gemini_create_cached_content(self, model, prefix, self.cached_content_ttl_secs)
+                .await
+                .map_err(|error| self.redact(error))?;
+        return Ok(Some(CachedContent { name, expires_at }));
+    }
+}
+
+// Smallest prefix the model caches
+fn min_cached_content_tokens(model: &str) -> u32 {
+    if model.starts_with("gemini-2.5-flash") {
+        return MIN_CACHED_CONTENT_TOKENS_FLASH_2_5;
+    }
+    return MIN_CACHED_CONTENT_TOKENS;
+}
 
 //Call large language model (i.e. Gemini)
-pub async fn call_gemini(message: &Message) -> Result<String, Box<dyn std::error::Error + Send>> {
-    dotenv().ok();
+pub async fn call_gemini(
+    client: &GeminiClient,
+    model: &str,
+    message: &Message,
+) -> Result<LlmResponse, LlmError> {
+    let response: reqwest::Response = client
+        .post(model, "generateContent", "")?
+        .json(&message)
+        .send()
+        .await?;
+    let response: GeminiResponse = check_response(response).await?.json().await?;
 
-    //Extract API key
-    let gemini_api_key: String =
-        env::var("GEMINI_API_KEY").expect("API-Key for Gemini not provided in the .env file!");
-    let gemini_url_prefix: String = "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.0-flash:generateContent?key=".to_string();
+    let usage_metadata: Option<UsageMetadata> = response.usage_metadata.clone();
+    let (text, finish_reason, function_calls) = text_from_gemini_response(response)?;
+    return Ok(LlmResponse {
+        usage: usage_from_gemini(usage_metadata.as_ref(), message, &text),
+        model: model.to_string(),
+        text,
+        finish_reason,
+        function_calls,
+    });
+}
 
-    // Combine url_prefix and api_key in single string
-    let gemini_url = format!("{}{}", gemini_url_prefix, gemini_api_key);
+// Token usage as reported by Gemini, estimated when the metadata is missing
+fn usage_from_gemini(
+    metadata: Option<&UsageMetadata>,
+    message: &Message,
+    text: &str,
+) -> TokenUsage {
+    let estimate: TokenUsage = estimate_usage(message, text);
+    return match metadata {
+        Some(metadata) => TokenUsage {
+            prompt_tokens: metadata
+                .prompt_token_count
+                .unwrap_or(estimate.prompt_tokens),
+            completion_tokens: metadata
+                .candidates_token_count
+                .unwrap_or(estimate.completion_tokens),
+        },
+        None => estimate,
+    };
+}
 
-    // Create headers
-    let mut gemini_headers: HeaderMap = HeaderMap::new();
-    gemini_headers.insert(
-        "Content-Type",
-        HeaderValue::from_str("application/json")
-            .map_err(|e| -> Box<dyn std::error::Error + Send> { Box::new(e) })?,
-    );
+// The text and function calls of the first usable candidate and why the model stopped, or
+// explain why there is no answer. Candidates are alternative answers, so they are never joined.
+pub fn text_from_gemini_response(
+    response: GeminiResponse,
+) -> Result<(String, FinishReason, Vec<FunctionCall>), LlmError> {
+    check_prompt_feedback(&response)?;
+
+    // When no candidate is usable the first one explains why
+    let mut answers = response.candidates.into_iter().map(answer_from_candidate);
+    let first: Result<(String, FinishReason, Vec<FunctionCall>), LlmError> =
+        answers.next().ok_or(LlmError::EmptyCandidates)?;
+    if first.is_ok() {
+        return first;
+    }
+    return answers.find(Result::is_ok).unwrap_or(first);
+}
+
+// The answer of a single candidate, checked against its own finish reason and safety ratings
+fn answer_from_candidate(
+    candidate: ResponseContent,
+) -> Result<(String, FinishReason, Vec<FunctionCall>), LlmError> {
+    let mut response_string: String = String::new();
+    let mut function_calls: Vec<FunctionCall> = vec![];
+    for parts in candidate.content.parts {
+        if let Some(text) = parts.text.as_deref() {
+            response_string.push_str(text);
+        }
+        function_calls.extend(parts.function_call);
+    }
+
+    let answered: bool = !response_string.is_empty() || !function_calls.is_empty();
+    let finish_reason: FinishReason =
+        finish_reason_from_gemini(candidate.finish_reason, &candidate.safety_ratings, answered)?;
+    return Ok((response_string, finish_reason, function_calls));
+}
+
+// A blocked prompt never gets candidates
+fn check_prompt_feedback(response: &GeminiResponse) -> Result<(), LlmError> {
+    if let Some(feedback) = &response.prompt_feedback
+        && let Some(reason) = &feedback.block_reason
+    {
+        return Err(LlmError::Blocked {
+            reason: blocked_reason(reason, &feedback.safety_ratings),
+        });
+    }
+    return Ok(());
+}
+
+// Turn the finish reason of an answer into an outcome. A blocked answer is an error, even when
+// part of the text came through, so it never ends up in the generated code.
+fn finish_reason_from_gemini(
+    finish_reason: Option<String>,
+    safety_ratings: &[SafetyRating],
+    answered: bool,
+) -> Result<FinishReason, LlmError> {
+    return match finish_reason.as_deref() {
+        Some(reason) if BLOCKED_FINISH_REASONS.contains(&reason) => Err(LlmError::Blocked {
+            reason: blocked_reason(reason, safety_ratings),
+        }),
+        _ if !answered => Err(LlmError::EmptyCandidates),
+        None | Some("STOP") | Some("FINISH_REASON_UNSPECIFIED") => Ok(FinishReason::Stop),
+        Some("MAX_TOKENS") => Ok(FinishReason::MaxTokens),
+        Some(reason) => Ok(FinishReason::Other(reason.to_string())),
+    };
+}
 
-    let client = reqwest::Client::builder()
-        .default_headers(gemini_headers)
-        .build()
-        .map_err(|e| -> Box<dyn std::error::Error + Send> { Box::new(e) })?;
+// The block reason with the categories which caused it, e.g. "SAFETY (HARM_CATEGORY_HARASSMENT: HIGH)"
+fn blocked_reason(reason: &str, safety_ratings: &[SafetyRating]) -> String {
+    let categories: Vec<String> = safety_ratings
+        .iter()
+        .filter(|rating| rating.blocked || matches!(rating.probability.as_str(), "MEDIUM" | "HIGH"))
+        .map(|rating| format!("{}: {}", rating.category, rating.probability))
+        .collect();
+    if categories.is_empty() {
+        return reason.to_string();
+    }
+    return format!("{} ({})", reason, categories.join(", "));
+}
 
-    let response: GeminiResponse = client
-        .post(gemini_url)
+//Call Gemini through streamGenerateContent, handing each text chunk to on_chunk
+pub async fn call_gemini_stream(
+    client: &GeminiClient,
+    model: &str,
+    message: &Message,
+    on_chunk: &mut ChunkCallback<'_>,
+) -> Result<LlmResponse, LlmError> {
+    let response: reqwest::Response = client
+        .post(model, "streamGenerateContent", "?alt=sse")?
         .json(&message)
         .send()
-        .await
-        .map_err(|e| -> Box<dyn std::error::Error + Send> { Box::new(e) })?
-        .json()
-        .await
-        .map_err(|e| -> Box<dyn std::error::Error + Send> { Box::new(e) })?;
+        .await?;
+    let mut response: reqwest::Response = check_response(response).await?;
 
+    // Every server sent event holds a partial GeminiResponse
+    let mut buffer: Vec<u8> = Vec::new();
     let mut response_string: String = String::new();
-    for candidate in response.candidates {
-        for parts in candidate.content.parts {
-            if let Some(text) = parts.text.as_deref() {
-                response_string.push_str(text);
+    let mut function_calls: Vec<FunctionCall> = vec![];
+    let mut finish_reason: Option<String> = None;
+    let mut safety_ratings: Vec<SafetyRating> = vec![];
+    let mut usage_metadata: Option<UsageMetadata> = None;
+    while let Some(bytes) = response.chunk().await? {
+        buffer.extend_from_slice(&bytes);
+        for data in drain_sse_data(&mut buffer) {
+            let event: GeminiResponse = serde_json::from_str(&data)?;
+            // The usage is reported as running totals, the last event has the final count
+            usage_metadata = event.usage_metadata.clone().or(usage_metadata);
+            check_prompt_feedback(&event)?;
+            // Only the first candidate is followed, the others are alternative answers
+            for candidate in event
+                .candidates
+                .into_iter()
+                .filter(|candidate| candidate.index == 0)
+            {
+                for parts in candidate.content.parts {
+                    if let Some(text) = parts.text.as_deref() {
+                        on_chunk(text);
+                        response_string.push_str(text);
+                    }
+                    function_calls.extend(parts.function_call);
+                }
+                finish_reason = candidate.finish_reason.or(finish_reason);
+                if !candidate.safety_ratings.is_empty() {
+                    safety_ratings = candidate.safety_ratings;
+                }
             }
         }
     }
-    return Ok(response_string);
+
+    let answered: bool = !response_string.is_empty() || !function_calls.is_empty();
+    let finish_reason: FinishReason =
+        finish_reason_from_gemini(finish_reason, &safety_ratings, answered)?;
+    return Ok(LlmResponse {
+        usage: usage_from_gemini(usage_metadata.as_ref(), message, &response_string),
+        model: model.to_string(),
+        text: response_string,
+        finish_reason,
+        function_calls,
+    });
+}
+
+// Take the payloads of all complete "data:" lines out of an SSE buffer
+fn drain_sse_data(buffer: &mut Vec<u8>) -> Vec<String> {
+    let mut data: Vec<String> = vec![];
+    while let Some(position) = buffer.iter().position(|byte| *byte == b'\n') {
+        let line: Vec<u8> = buffer.drain(..=position).collect();
+        let line: String = String::from_utf8_lossy(&line).trim().to_string();
+        if let Some(payload) = line.strip_prefix("data:") {
+            data.push(payload.trim().to_string());
+        }
+    }
+    return data;
+}
+
+// Ask Gemini how many tokens a message will consume
+pub async fn call_gemini_count_tokens(
+    client: &GeminiClient,
+    model: &str,
+    message: &Message,
+) -> Result<u32, LlmError> {
+    // Count the whole request, the system instruction included
+    let mut request: serde_json::Value = serde_json::to_value(message)?;
+    request["model"] = serde_json::Value::String(format!("models/{}", model));
+    let body: serde_json::Value = serde_json::json!({ "generateContentRequest": request });
+
+    let response: reqwest::Response = client
+        .post(model, "countTokens", "")?
+        .json(&body)
+        .send()
+        .await?;
+    let response: CountTokensResponse = check_response(response).await?.json().await?;
+
+    return Ok(response.total_tokens);
+}
+
+// Upload a prefix for later requests to the model, returns the name to reference it by
+pub async fn call_gemini_create_cached_content(
+    client: &GeminiClient,
+    model: &str,
+    prefix: &Message,
+    ttl_secs: u64,
+) -> Result<String, LlmError> {
+    let mut request: serde_json::Value = serde_json::to_value(prefix)?;
+    request["model"] = serde_json::Value::String(format!("models/{}", model));
+    request["ttl"] = serde_json::Value::String(format!("{}s", ttl_secs));
+
+    let response: reqwest::Response = client
+        .post_to("cachedContents")?
+        .json(&request)
+        .send()
+        .await?;
+    let response: CachedContentResponse = check_response(response).await?.json().await?;
+
+    return Ok(response.name);
 }
 
 #[tokio::test]
 async fn test_call_gemini() {
+    use crate::test_support::mock_gemini::{MockGemini, MockReply};
+
+    let mock: MockGemini = MockGemini::start().await;
+    mock.respond(
+        "Hallo",
+        MockReply::Text("Hallo! Dit is een kort antwoord.".to_string()),
+    );
     let message = "Hallo, dit is een test. Kan je een kort antwoord geven?";
 
     let gemini_prompt: Message = Message {
-        contents: vec![MessagePart {
-            parts: vec![MessagePartText {
-                text: message.to_string(),
-            }],
-        }],
+        contents: vec![MessagePart::user(message.to_string())],
         generation_config: Some(GenerationConfig {
             temperature: Some(0.7),
             max_output_tokens: Some(500),
+            ..Default::default()
         }),
+        ..Default::default()
     };
 
-    let res = call_gemini(&gemini_prompt).await;
+    let client: GeminiClient = GeminiClient::new(&mock.config()).unwrap();
+    let res = call_gemini(&client, "gemini-2.0-flash", &gemini_prompt).await;
     println!("Test result: {:#?}", res);
-    if let Ok(_res_str) = res {
-        assert!(true);
-    } else {
-        assert!(false);
-    }
+    assert_eq!(res.unwrap().text, "Hallo! Dit is een kort antwoord.");
+
+    let request: serde_json::Value = mock.requests()[0].json();
+    assert_eq!(request["contents"][0]["parts"][0]["text"], message);
+    assert_eq!(request["generation_config"]["temperature"], 0.7);
+    assert_eq!(request["generation_config"]["max_output_tokens"], 500);
+}
+
+#[test]
+fn test_text_from_gemini_response() {
+    let response: GeminiResponse = serde_json::from_str(
+        r#"{
+            "candidates": [
+                { "content": { "parts": [{ "text": "Hallo" }, { "text": " daar" }], "role": "model" }, "finishReason": "STOP" }
+            ]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        text_from_gemini_response(response),
+        Ok(("Hallo daar".to_string(), FinishReason::Stop, vec![]))
+    );
+
+    let response: GeminiResponse = serde_json::from_str(
+        r#"{
+            "candidates": [
+                { "content": { "parts": [{ "text": "fn main() {" }], "role": "model" }, "finishReason": "MAX_TOKENS" }
+            ]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        text_from_gemini_response(response),
+        Ok(("fn main() {".to_string(), FinishReason::MaxTokens, vec![]))
+    );
+
+    // A function call is an answer without text
+    let response: GeminiResponse = serde_json::from_str(
+        r#"{
+            "candidates": [{
+                "content": {
+                    "parts": [{ "functionCall": { "name": "read_file", "args": { "path": "src/main.rs" } } }],
+                    "role": "model"
+                },
+                "finishReason": "STOP"
+            }]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        text_from_gemini_response(response),
+        Ok((
+            String::new(),
+            FinishReason::Stop,
+            vec![FunctionCall {
+                name: "read_file".to_string(),
+                args: serde_json::json!({ "path": "src/main.rs" }),
+            }]
+        ))
+    );
+
+    // Partial text of a blocked answer is not returned
+    let response: GeminiResponse = serde_json::from_str(
+        r#"{
+            "candidates": [{
+                "content": { "parts": [{ "text": "fn main() {" }], "role": "model" },
+                "finishReason": "SAFETY",
+                "safetyRatings": [
+                    { "category": "HARM_CATEGORY_HARASSMENT", "probability": "NEGLIGIBLE" },
+                    { "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH", "blocked": true }
+                ]
+            }]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        text_from_gemini_response(response),
+        Err(LlmError::Blocked {
+            reason: "SAFETY (HARM_CATEGORY_DANGEROUS_CONTENT: HIGH)".to_string()
+        })
+    );
+
+    let response: GeminiResponse =
+        serde_json::from_str(r#"{ "promptFeedback": { "blockReason": "SAFETY" } }"#).unwrap();
+    assert_eq!(
+        text_from_gemini_response(response),
+        Err(LlmError::Blocked {
+            reason: "SAFETY".to_string()
+        })
+    );
+
+    let response: GeminiResponse = serde_json::from_str(r#"{ "candidates": [] }"#).unwrap();
+    assert_eq!(
+        text_from_gemini_response(response),
+        Err(LlmError::EmptyCandidates)
+    );
+
+    // Alternative answers are not glued together, the first usable one is the answer
+    let response: GeminiResponse = serde_json::from_str(
+        r#"{
+            "candidates": [
+                { "index": 0, "content": { "parts": [{ "text": "[\"https://a.example\"]" }] }, "finishReason": "STOP" },
+                { "index": 1, "content": { "parts": [{ "text": "[\"https://b.example\"]" }] }, "finishReason": "STOP" }
+            ]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        text_from_gemini_response(response),
+        Ok((
+            "[\"https://a.example\"]".to_string(),
+            FinishReason::Stop,
+            vec![]
+        ))
+    );
+
+    // A blocked candidate does not block the others, their finish reasons are their own
+    let response: GeminiResponse = serde_json::from_str(
+        r#"{
+            "candidates": [
+                { "index": 0, "finishReason": "SAFETY" },
+                { "index": 1, "content": { "parts": [{ "text": "fn main() {" }] }, "finishReason": "MAX_TOKENS" }
+            ]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        text_from_gemini_response(response),
+        Ok(("fn main() {".to_string(), FinishReason::MaxTokens, vec![]))
+    );
+    let response: GeminiResponse = serde_json::from_str(
+        r#"{ "candidates": [{ "index": 0, "finishReason": "SAFETY" }, { "index": 1, "finishReason": "STOP" }] }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        text_from_gemini_response(response),
+        Err(LlmError::Blocked {
+            reason: "SAFETY".to_string()
+        })
+    );
+}
+
+#[test]
+fn test_drain_sse_data() {
+    let mut buffer: Vec<u8> = b"data: {\"candidates\": []}\r\n\r\ndata: {\"candidates\"".to_vec();
+    assert_eq!(drain_sse_data(&mut buffer), vec![r#"{"candidates": []}"#]);
+
+    // The incomplete event stays in the buffer until the rest arrives
+    buffer.extend_from_slice(b": []}\r\n\r\n");
+    assert_eq!(drain_sse_data(&mut buffer), vec![r#"{"candidates": []}"#]);
+    assert!(buffer.is_empty());
+}
+
+#[tokio::test]
+async fn test_gemini_provider_against_mock() {
+    use crate::test_support::mock_gemini::{MockGemini, MockReply};
+    use std::collections::BTreeMap;
+    use std::time::{Duration, Instant};
+
+    let mock: MockGemini = MockGemini::start().await;
+    mock.respond("greeting", MockReply::Text("Hallo daar".to_string()))
+        .respond(
+            "limited",
+            MockReply::RateLimited {
+                retry_after: Some(7),
+            },
+        )
+        .respond("empty", MockReply::EmptyCandidates);
+    let provider: GeminiClient = GeminiClient::new(&mock.config()).unwrap();
+    let message = |text: &str| Message {
+        contents: vec![MessagePart::user(text.to_string())],
+        ..Default::default()
+    };
+    let context: CallContext = CallContext::new("Tester", "greeting");
+    let expected: LlmResponse = LlmResponse {
+        text: "Hallo daar".to_string(),
+        model: "gemini-2.0-flash".to_string(),
+        usage: TokenUsage {
+            prompt_tokens: 2,
+            completion_tokens: 3,
+        },
+        finish_reason: FinishReason::Stop,
+        function_calls: vec![],
+    };
+
+    assert_eq!(
+        provider.generate(&message("greeting"), &context).await,
+        Ok(expected.clone())
+    );
+
+    let mut chunks: Vec<String> = vec![];
+    let streamed = provider
+        .generate_stream(&message("greeting"), &context, &mut |chunk| {
+            chunks.push(chunk.to_string())
+        })
+        .await;
+    assert_eq!(streamed, Ok(expected));
+    assert_eq!(chunks, vec!["Hallo", " daar"]);
+
+    assert_eq!(
+        provider.generate(&message("limited"), &context).await,
+        Err(LlmError::RateLimited {
+            retry_after: Some(Duration::from_secs(7))
+        })
+    );
+    assert_eq!(
+        provider.generate(&message("empty"), &context).await,
+        Err(LlmError::EmptyCandidates)
+    );
+    assert_eq!(
+        provider.count_tokens(&message("greeting"), &context).await,
+        Ok(2)
+    );
+
+    let requests = mock.requests();
+    assert_eq!(requests[0].path, "/models/gemini-2.0-flash:generateContent");
+    assert_eq!(
+        requests[1].path,
+        "/models/gemini-2.0-flash:streamGenerateContent?alt=sse"
+    );
+    assert_eq!(requests[0].header("x-goog-api-key"), Some("mock-api-key"));
+}
+
+#[tokio::test]
+async fn test_gemini_model_per_ai_function() {
+    use crate::test_support::mock_gemini::{MockGemini, MockReply};
+
+    let mock: MockGemini = MockGemini::start().await;
+    mock.respond("goal", MockReply::Text("Build a website".to_string()))
+        .respond("code", MockReply::Text("fn main() {}".to_string()));
+    let mut config: GeminiConfig = mock.config();
+    config.models.insert(
+        "print_backend_webserver_code".to_string(),
+        "gemini-2.5-pro".to_string(),
+    );
+    let provider: GeminiClient = GeminiClient::new(&config).unwrap();
+    let message = |text: &str| Message {
+        contents: vec![MessagePart::user(text.to_string())],
+        ..Default::default()
+    };
+
+    let goal: LlmResponse = provider
+        .generate(
+            &message("goal"),
+            &CallContext::new("Project Manager", "convert_user_input_to_goal"),
+        )
+        .await
+        .unwrap();
+    let code: LlmResponse = provider
+        .generate(
+            &message("code"),
+            &CallContext::new("Backend Developer", "print_backend_webserver_code"),
+        )
+        .await
+        .unwrap();
+
+    // The usage report prices every answer with the model which gave it
+    assert_eq!(goal.model, "gemini-2.0-flash");
+    assert_eq!(code.model, "gemini-2.5-pro");
+    let requests = mock.requests();
+    assert!(
+        requests[0]
+            .path
+            .starts_with("/models/gemini-2.0-flash:generateContent")
+    );
+    assert!(
+        requests[1]
+            .path
+            .starts_with("/models/gemini-2.5-pro:generateContent")
+    );
+}
+
+#[tokio::test]
+async fn test_gemini_api_key_never_printed() {
+    use crate::test_support::mock_gemini::MockGemini;
+
+    let mock: MockGemini = MockGemini::start().await;
+    let provider: GeminiClient = GeminiClient::new(&mock.config()).unwrap();
+    assert!(!format!("{:?}", provider).contains("mock-api-key"));
+    assert!(!format!("{:?}", mock.config()).contains("mock-api-key"));
+
+    // An error body echoing the key is redacted
+    let echoed: LlmError = provider.redact(LlmError::Http {
+        status: 400,
+        body: "API key mock-api-key not valid".to_string(),
+    });
+    assert_eq!(
+        echoed,
+        LlmError::Http {
+            status: 400,
+            body: "API key [REDACTED] not valid".to_string()
+        }
+    );
+
+    // Without the key header the server refuses the request
+    let mut config: GeminiConfig = mock.config();
+    config.api_key = None;
+    let provider: GeminiClient = GeminiClient::new(&config).unwrap();
+    let message: Message = Message {
+        contents: vec![],
+        ..Default::default()
+    };
+    assert_eq!(
+        provider.generate(&message, &CallContext::default()).await,
+        Err(LlmError::MissingCredentials("GEMINI_API_KEY".to_string()))
+    );
 }
@@ -0,0 +1,291 @@
+#![allow(unused)]
+use crate::apis::llm_error::LlmError;
+use crate::apis::llm_provider::{
+    CachedContent, CallContext, ChunkCallback, LlmProvider, LlmResponse,
+};
+use crate::apis::usage::UsageLedger;
+use crate::models::general::llm::Message;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Recorded request/response pairs, keyed by a hash of the request
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Cassette {
+    pub entries: BTreeMap<String, CassetteEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CassetteEntry {
+    pub method: String,
+    pub request: serde_json::Value,
+    pub response: serde_json::Value,
+}
+
+impl Cassette {
+    // Read a cassette file, a missing file is an empty cassette
+    pub fn load(path: &Path) -> Self {
+        return match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .unwrap_or_else(|e| panic!("Failed to parse cassette {}: {}", path.display(), e)),
+            Err(_) => Cassette::default(),
+        };
+    }
+
+    pub fn save(&self, path: &Path) {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).expect("Failed to create cassette directory!");
+        }
+        let contents: String =
+            serde_json::to_string_pretty(self).expect("Failed to serialize cassette");
+        std::fs::write(path, contents).expect("Failed to write cassette file!");
+    }
+
+    pub fn get(&self, method: &str, message: &Message) -> Option<&serde_json::Value> {
+        return self
+            .entries
+            .get(&cassette_key(method, message))
+            .map(|entry| &entry.response);
+    }
+
+    pub fn insert(&mut self, method: &str, message: &Message, response: serde_json::Value) {
+        self.entries.insert(
+            cassette_key(method, message),
+            CassetteEntry {
+                method: method.to_string(),
+                request: serde_json::to_value(message).unwrap_or_default(),
+                response,
+            },
+        );
+    }
+}
+
+// Stable key for a request: hash of the method and the serialized message
+pub fn cassette_key(method: &str, message: &Message) -> String {
+    let serialized: String = serde_json::to_string(message).unwrap_or_default();
+    return stable_hash(&[method, &serialized]);
+}
+
+// FNV-1a hash of the parts joined by newlines, the same on every machine and every run
+pub fn stable_hash(parts: &[&str]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in parts.join("\n").bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return format!("{:016x}", hash);
+}
+
+// Passes calls to the inner provider and writes every answer to the cassette file
+#[derive(Debug)]
+pub struct RecordingProvider {
+    inner: Arc<dyn LlmProvider>,
+    path: PathBuf,
+    cassette: Mutex<Cassette>,
+    // Answer from the cassette when it already holds the request ("record once")
+    replay_existing: bool,
+}
+
+impl RecordingProvider {
+    pub fn new(inner: Arc<dyn LlmProvider>, path: impl Into<PathBuf>) -> Self {
+        let path: PathBuf = path.into();
+        let cassette: Cassette = Cassette::load(&path);
+        return Self {
+            inner,
+            path,
+            cassette: Mutex::new(cassette),
+            replay_existing: false,
+        };
+    }
+
+    // Only call the inner provider for requests which are not on the cassette yet
+    pub fn once(inner: Arc<dyn LlmProvider>, path: impl Into<PathBuf>) -> Self {
+        let mut provider: RecordingProvider = Self::new(inner, path);
+        provider.replay_existing = true;
+        return provider;
+    }
+
+    fn recorded(&self, method: &str, message: &Message) -> Option<serde_json::Value> {
+        if !self.replay_existing {
+            return None;
+        }
+        return self.cassette.lock().unwrap().get(method, message).cloned();
+    }
+
+    fn record(&self, method: &str, message: &Message, response: serde_json::Value) {
+        let mut cassette = self.cassette.lock().unwrap();
+        cassette.insert(method, message, response);
+        cassette.save(&self.path);
+    }
+}
+
+#[async_trait]
+impl LlmProvider for RecordingProvider {
+    async fn generate(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<LlmResponse, LlmError> {
+        if let Some(response) = self.recorded("generate", message) {
+            return Ok(serde_json::from_value(response)?);
+        }
+        let response: LlmResponse = self.inner.generate(message, context).await?;
+        self.record("generate", message, serde_json::to_value(&response)?);
+        return Ok(response);
+    }
+
+    async fn generate_stream(
+        &self,
+        message: &Message,
+        context: &CallContext,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<LlmResponse, LlmError> {
+        // Streamed answers are replayed through generate, so record them under that method
+        if let Some(response) = self.recorded("generate", message) {
+            let response: LlmResponse = serde_json::from_value(response)?;
+            on_chunk(&response.text);
+            return Ok(response);
+        }
+        let response: LlmResponse = self
+            .inner
+            .generate_stream(message, context, on_chunk)
+            .await?;
+        self.record("generate", message, serde_json::to_value(&response)?);
+        return Ok(response);
+    }
+
+    async fn count_tokens(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<u32, LlmError> {
+        if let Some(tokens) = self.recorded("count_tokens", message) {
+            return Ok(serde_json::from_value(tokens)?);
+        }
+        let tokens: u32 = self.inner.count_tokens(message, context).await?;
+        self.record("count_tokens", message, serde_json::json!(tokens));
+        return Ok(tokens);
+    }
+
+    // Cache names differ per run and would make the recorded requests unmatchable, so the
+    // recordings always carry the whole prompt
+    async fn create_cached_content(
+        &self,
+        prefix: &Message,
+        context: &CallContext,
+    ) -> Result<Option<CachedContent>, LlmError> {
+        return Ok(None);
+    }
+
+    fn usage_ledger(&self) -> Option<Arc<UsageLedger>> {
+        return self.inner.usage_ledger();
+    }
+}
+
+// Serves answers from a cassette file without any network access
+#[derive(Debug)]
+pub struct ReplayProvider {
+    path: PathBuf,
+    cassette: Cassette,
+}
+
+impl ReplayProvider {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path: PathBuf = path.into();
+        let cassette: Cassette = Cassette::load(&path);
+        return Self { path, cassette };
+    }
+
+    fn replay(&self, method: &str, message: &Message) -> Result<&serde_json::Value, LlmError> {
+        return self.cassette.get(method, message).ok_or_else(|| {
+            LlmError::MissingRecording(format!(
+                "{} has no {} recording for request {}",
+                self.path.display(),
+                method,
+                cassette_key(method, message)
+            ))
+        });
+    }
+}
+
+#[async_trait]
+impl LlmProvider for ReplayProvider {
+    async fn generate(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<LlmResponse, LlmError> {
+        let response: &serde_json::Value = self.replay("generate", message)?;
+        return Ok(serde_json::from_value(response.clone())?);
+    }
+
+    async fn count_tokens(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<u32, LlmError> {
+        let response: &serde_json::Value = self.replay("count_tokens", message)?;
+        return Ok(serde_json::from_value(response.clone())?);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::general::llm::{MessagePart, MessagePartText};
+    use crate::test_support::scripted_provider::ScriptedProvider;
+
+    fn message(text: &str) -> Message {
+        return Message {
+            contents: vec![MessagePart::user(text.to_string())],
+            ..Default::default()
+        };
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("autogemini_cassette_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let inner: Arc<ScriptedProvider> = Arc::new(ScriptedProvider::new(vec![Ok(
+            "build a website that ...".to_string(),
+        )]));
+        let recorder: RecordingProvider = RecordingProvider::new(inner, &path);
+        let context: CallContext = CallContext::default();
+        let recorded: LlmResponse = recorder
+            .generate(&message("Build me a blog"), &context)
+            .await
+            .unwrap();
+
+        let replayer: ReplayProvider = ReplayProvider::new(&path);
+        assert_eq!(
+            replayer
+                .generate(&message("Build me a blog"), &context)
+                .await,
+            Ok(recorded)
+        );
+        assert!(matches!(
+            replayer
+                .generate(&message("Build me a shop"), &context)
+                .await,
+            Err(LlmError::MissingRecording(_))
+        ));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_cassette_key_is_stable() {
+        assert_eq!(
+            cassette_key("generate", &message("Hello")),
+            cassette_key("generate", &message("Hello"))
+        );
+        assert_ne!(
+            cassette_key("generate", &message("Hello")),
+            cassette_key("count_tokens", &message("Hello"))
+        );
+    }
+}
@@ -0,0 +1,223 @@
+#![allow(unused)]
+use crate::helpers::secret::ApiKey;
+use reqwest::StatusCode;
+use reqwest::header::RETRY_AFTER;
+use std::fmt;
+use std::time::Duration;
+
+// Everything that can go wrong while talking to a large language model
+#[derive(Debug, Clone, PartialEq)]
+pub enum LlmError {
+    // The API key (environment variable) is not available
+    MissingCredentials(String),
+    // The server answered with an unsuccessful status code
+    Http { status: u16, body: String },
+    // The server answered with 429 Too Many Requests
+    RateLimited { retry_after: Option<Duration> },
+    // The server answered with 503 Service Unavailable (model overloaded)
+    Overloaded { retry_after: Option<Duration> },
+    // The prompt or the answer was blocked, e.g. by the safety filters
+    Blocked { reason: String },
+    // The model answered without any candidate text
+    EmptyCandidates,
+    // The answer did not have the expected (JSON) shape
+    Decode(String),
+    // The request never got an answer (connection, timeout, ...)
+    Network(String),
+    // A replayed cassette holds no answer for the request
+    MissingRecording(String),
+    // The budget of the run is used up
+    BudgetExceeded(String),
+    // The answer was still cut off at the output token limit after asking to continue it
+    Truncated(String),
+    // The model kept calling tools instead of answering
+    ToolLoop { rounds: u8 },
+}
+
+impl fmt::Display for LlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingCredentials(variable) => {
+                write!(
+                    f,
+                    "API key not provided, please set {} in the .env file",
+                    variable
+                )
+            }
+            Self::Http { status, body } => {
+                write!(f, "LLM request failed with status {}: {}", status, body)
+            }
+            Self::RateLimited {
+                retry_after: Some(retry_after),
+            } => {
+                write!(
+                    f,
+                    "LLM request was rate limited, retry after {:?}",
+                    retry_after
+                )
+            }
+            Self::RateLimited { retry_after: None } => write!(f, "LLM request was rate limited"),
+            Self::Overloaded { .. } => write!(f, "LLM service is temporarily unavailable"),
+            Self::Blocked { reason } => write!(f, "LLM request was blocked: {}", reason),
+            Self::EmptyCandidates => write!(f, "LLM returned no candidates"),
+            Self::Decode(error) => write!(f, "Failed to decode LLM response: {}", error),
+            Self::Network(error) => write!(f, "Failed to reach the LLM: {}", error),
+            Self::MissingRecording(error) => write!(f, "No recorded LLM answer: {}", error),
+            Self::BudgetExceeded(usage) => write!(f, "LLM budget exceeded: {}", usage),
+            Self::Truncated(error) => write!(f, "LLM answer is incomplete: {}", error),
+            Self::ToolLoop { rounds } => {
+                write!(f, "LLM was still calling tools after {} rounds", rounds)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LlmError {}
+
+impl LlmError {
+    // Errors which may go away when the same request is sent again
+    pub fn is_transient(&self) -> bool {
+        return match self {
+            Self::RateLimited { .. } | Self::Overloaded { .. } | Self::Network(_) => true,
+            Self::Http { status, .. } => *status == 408 || *status >= 500,
+            Self::EmptyCandidates => true,
+            Self::MissingCredentials(_)
+            | Self::Blocked { .. }
+            | Self::Decode(_)
+            | Self::MissingRecording(_)
+            | Self::BudgetExceeded(_)
+            | Self::Truncated(_)
+            | Self::ToolLoop { .. } => false,
+        };
+    }
+
+    // The same error with the key removed from the messages of the server and the transport
+    pub fn redacted(self, api_key: &ApiKey) -> Self {
+        return match self {
+            Self::Http { status, body } => Self::Http {
+                status,
+                body: api_key.redact(&body),
+            },
+            Self::Decode(error) => Self::Decode(api_key.redact(&error)),
+            Self::Network(error) => Self::Network(api_key.redact(&error)),
+            error => error,
+        };
+    }
+
+    // How long the server asked us to wait before trying again
+    pub fn retry_after(&self) -> Option<Duration> {
+        return match self {
+            Self::RateLimited { retry_after } | Self::Overloaded { retry_after } => *retry_after,
+            _ => None,
+        };
+    }
+}
+
+impl From<reqwest::Error> for LlmError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_decode() {
+            return Self::Decode(error.to_string());
+        }
+        return match error.status() {
+            Some(StatusCode::TOO_MANY_REQUESTS) => Self::RateLimited { retry_after: None },
+            Some(StatusCode::SERVICE_UNAVAILABLE) => Self::Overloaded { retry_after: None },
+            Some(status) => Self::Http {
+                status: status.as_u16(),
+                body: error.to_string(),
+            },
+            None => Self::Network(error.to_string()),
+        };
+    }
+}
+
+impl From<serde_json::Error> for LlmError {
+    fn from(error: serde_json::Error) -> Self {
+        return Self::Decode(error.to_string());
+    }
+}
+
+// Turn an unsuccessful response into the matching error, pass successful ones through
+pub async fn check_response(response: reqwest::Response) -> Result<reqwest::Response, LlmError> {
+    let status: StatusCode = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return Err(LlmError::RateLimited {
+            retry_after: retry_after(&response),
+        });
+    }
+    if status == StatusCode::SERVICE_UNAVAILABLE {
+        return Err(LlmError::Overloaded {
+            retry_after: retry_after(&response),
+        });
+    }
+
+    let body: String = response.text().await.unwrap_or_default();
+    return Err(LlmError::Http {
+        status: status.as_u16(),
+        body,
+    });
+}
+
+// Read the Retry-After header (in seconds) of a response
+pub fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    return response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::stub_server::{StubResponse, StubServer};
+
+    #[tokio::test]
+    async fn test_check_response() {
+        let server: StubServer = StubServer::start(|request| match request.path.as_str() {
+            "/limited" => {
+                StubResponse::json(429, serde_json::json!({})).with_header("Retry-After", "7")
+            }
+            "/bad" => StubResponse::json(400, serde_json::json!({ "error": "bad request" })),
+            _ => StubResponse::json(200, serde_json::json!({})),
+        })
+        .await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(format!("{}/limited", server.base_url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            check_response(response).await.unwrap_err(),
+            LlmError::RateLimited {
+                retry_after: Some(Duration::from_secs(7))
+            }
+        );
+
+        let response = client
+            .get(format!("{}/bad", server.base_url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            check_response(response).await.unwrap_err(),
+            LlmError::Http {
+                status: 400,
+                body: r#"{"error":"bad request"}"#.to_string()
+            }
+        );
+
+        let response = client
+            .get(format!("{}/ok", server.base_url))
+            .send()
+            .await
+            .unwrap();
+        assert!(check_response(response).await.is_ok());
+    }
+}
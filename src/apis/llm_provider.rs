@@ -0,0 +1,190 @@
+#![allow(unused)]
+use crate::apis::budget::BudgetProvider;
+use crate::apis::cache::CachedProvider;
+use crate::apis::call_ollama::OllamaProvider;
+use crate::apis::call_openai::OpenAiProvider;
+use crate::apis::call_request::GeminiClient;
+use crate::apis::llm_error::LlmError;
+use crate::apis::rate_limit::{RateLimitedProvider, RateLimiter};
+use crate::apis::retry::RetryProvider;
+use crate::apis::usage::{MeteredProvider, UsageLedger};
+use crate::helpers::config::{AppConfig, ProviderKind};
+use crate::helpers::json_repair::decode_llm_json;
+use crate::models::general::llm::{FunctionCall, Message};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Instant;
+
+// Receives the pieces of a streamed answer
+pub type ChunkCallback<'a> = dyn FnMut(&str) + Send + 'a;
+
+// Who is asking the model: the agent and the AI function it runs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CallContext {
+    pub agent: String,
+    pub function: String,
+}
+
+impl CallContext {
+    pub fn new(agent: &str, function: &str) -> Self {
+        return Self {
+            agent: agent.to_string(),
+            function: function.to_string(),
+        };
+    }
+}
+
+// Tokens consumed by a single call
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+impl TokenUsage {
+    pub fn total_tokens(&self) -> u32 {
+        return self.prompt_tokens + self.completion_tokens;
+    }
+}
+
+// Why the model stopped answering. Answers stopped by the safety filters are LlmError::Blocked.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub enum FinishReason {
+    // The answer is complete
+    #[default]
+    Stop,
+    // The answer was cut off at the maximum number of output tokens
+    MaxTokens,
+    // Any other reason reported by the backend, e.g. MALFORMED_FUNCTION_CALL
+    Other(String),
+}
+
+// A prefix uploaded to the backend, referenced by name until it expires
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedContent {
+    pub name: String,
+    pub expires_at: Instant,
+}
+
+// The answer of a model together with what it cost
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LlmResponse {
+    pub text: String,
+    pub model: String,
+    pub usage: TokenUsage,
+    #[serde(default)]
+    pub finish_reason: FinishReason,
+    // Local functions the model wants called before it answers
+    #[serde(default)]
+    pub function_calls: Vec<FunctionCall>,
+}
+
+// Common interface for every large language model backend used by the agents
+#[async_trait]
+pub trait LlmProvider: Debug + Send + Sync {
+    // Generate a plain text answer for the message
+    async fn generate(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<LlmResponse, LlmError>;
+
+    // Generate an answer that must be JSON and return it parsed
+    async fn generate_structured(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<serde_json::Value, LlmError> {
+        let response: LlmResponse = self.generate(message, context).await?;
+        return Ok(decode_llm_json(&response.text)?);
+    }
+
+    // Generate a plain text answer, handing every piece of text to on_chunk as soon as it arrives.
+    // Backends without streaming support deliver the whole answer as a single chunk.
+    async fn generate_stream(
+        &self,
+        message: &Message,
+        context: &CallContext,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<LlmResponse, LlmError> {
+        let response: LlmResponse = self.generate(message, context).await?;
+        on_chunk(&response.text);
+        return Ok(response);
+    }
+
+    // Count the number of tokens the message will consume
+    async fn count_tokens(&self, message: &Message, context: &CallContext)
+    -> Result<u32, LlmError>;
+
+    // Upload a prefix shared by the coming calls of an AI function, the returned name goes in
+    // Message.cached_content until it expires. None when the backend can not cache the prefix.
+    async fn create_cached_content(
+        &self,
+        prefix: &Message,
+        context: &CallContext,
+    ) -> Result<Option<CachedContent>, LlmError> {
+        return Ok(None);
+    }
+
+    // The usage of this provider stack, if it is metered
+    fn usage_ledger(&self) -> Option<Arc<UsageLedger>> {
+        return None;
+    }
+}
+
+// Create the provider selected in the configuration, kept within the rate limits, wrapped in the
+// retry policy, metered, limited to the budget of the run and, when enabled, behind the cache
+pub fn build_provider(config: &AppConfig) -> Result<Arc<dyn LlmProvider>, LlmError> {
+    let backend: Arc<dyn LlmProvider> = match config.provider {
+        ProviderKind::Gemini => Arc::new(GeminiClient::new(&config.gemini)?),
+        ProviderKind::OpenAi => Arc::new(OpenAiProvider::new(&config.openai)?),
+        ProviderKind::Ollama => Arc::new(OllamaProvider::new(&config.ollama)?),
+    };
+    let limiter: Arc<RateLimiter> = Arc::new(RateLimiter::new(&config.rate_limit));
+    let limited: Arc<dyn LlmProvider> = Arc::new(RateLimitedProvider::new(backend, limiter));
+    let retrying: Arc<dyn LlmProvider> =
+        Arc::new(RetryProvider::new(limited, config.retry.clone()));
+    let ledger: Arc<UsageLedger> = Arc::new(UsageLedger::new(config.pricing.clone()));
+    let metered: Arc<dyn LlmProvider> =
+        Arc::new(MeteredProvider::new(retrying, Arc::clone(&ledger)));
+    let budgeted: Arc<dyn LlmProvider> =
+        Arc::new(BudgetProvider::new(metered, ledger, config.budget.clone()));
+    if !config.cache.enabled {
+        return Ok(budgeted);
+    }
+
+    let (model, models): (String, BTreeMap<String, String>) = match config.provider {
+        ProviderKind::Gemini => (config.gemini.model.clone(), config.gemini.models.clone()),
+        ProviderKind::OpenAi => (config.openai.model.clone(), BTreeMap::new()),
+        ProviderKind::Ollama => (config.ollama.model.clone(), BTreeMap::new()),
+    };
+    return Ok(Arc::new(CachedProvider::new(
+        budgeted,
+        config.cache.clone(),
+        model,
+        models,
+    )));
+}
+
+// Rough token estimate (4 characters per token) for backends without a counting endpoint
+pub fn estimate_tokens(message: &Message) -> u32 {
+    let characters: usize = message
+        .contents
+        .iter()
+        .chain(message.system_instruction.iter())
+        .flat_map(|content| content.parts.iter())
+        .map(|part| part.characters())
+        .sum();
+    return characters.div_ceil(4) as u32;
+}
+
+// Usage for backends which do not report it: estimated from the prompt and the answer
+pub fn estimate_usage(message: &Message, answer: &str) -> TokenUsage {
+    return TokenUsage {
+        prompt_tokens: estimate_tokens(message),
+        completion_tokens: answer.chars().count().div_ceil(4) as u32,
+    };
+}
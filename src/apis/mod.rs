@@ -1 +1,11 @@
+pub mod budget;
+pub mod cache;
+pub mod call_ollama;
+pub mod call_openai;
 pub mod call_request;
+pub mod cassette;
+pub mod llm_error;
+pub mod llm_provider;
+pub mod rate_limit;
+pub mod retry;
+pub mod usage;
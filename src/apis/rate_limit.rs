@@ -0,0 +1,305 @@
+#![allow(unused)]
+use crate::apis::llm_error::LlmError;
+use crate::apis::llm_provider::{
+    CachedContent, CallContext, ChunkCallback, LlmProvider, LlmResponse, estimate_tokens,
+};
+use crate::apis::usage::UsageLedger;
+use crate::helpers::command_line::PrintCommand;
+use crate::models::general::llm::Message;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const RATE_LIMIT_POSITION: &str = "LLM rate limit";
+
+// Client side limits, e.g. the free tier of gemini-2.0-flash allows 15 requests and 1M tokens
+// per minute. A missing limit, or a limit of 0, means unlimited.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct RateLimit {
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u32>,
+}
+
+// Classic token bucket: holds up to `capacity` and refills `capacity` per minute.
+// The level may go negative when more was used than announced, later calls then wait longer.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    level: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn per_minute(capacity: u32, now: Instant) -> Self {
+        return Self {
+            capacity: capacity as f64,
+            level: capacity as f64,
+            updated: now,
+        };
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed: f64 = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.level = (self.level + elapsed * self.capacity / 60.0).min(self.capacity);
+        self.updated = now;
+    }
+
+    // How long to wait until `amount` is available, zero when it is available now
+    pub fn wait_for(&mut self, amount: f64, now: Instant) -> Duration {
+        self.refill(now);
+        // A single request larger than the bucket only has to wait for a full bucket
+        let missing: f64 = amount.min(self.capacity) - self.level;
+        if missing <= 0.0 {
+            return Duration::ZERO;
+        }
+        return Duration::from_secs_f64(missing * 60.0 / self.capacity);
+    }
+
+    pub fn take(&mut self, amount: f64, now: Instant) {
+        self.refill(now);
+        self.level -= amount;
+    }
+}
+
+// Shared limiter for requests and tokens per minute. Callers queue until both buckets allow them.
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests: Option<Mutex<TokenBucket>>,
+    tokens: Option<Mutex<TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: &RateLimit) -> Self {
+        let now: Instant = Instant::now();
+        return Self {
+            requests: limit
+                .requests_per_minute
+                .filter(|capacity| *capacity > 0)
+                .map(|capacity| Mutex::new(TokenBucket::per_minute(capacity, now))),
+            tokens: limit
+                .tokens_per_minute
+                .filter(|capacity| *capacity > 0)
+                .map(|capacity| Mutex::new(TokenBucket::per_minute(capacity, now))),
+        };
+    }
+
+    // Wait until a request using about `tokens` tokens is allowed and take it from the buckets
+    pub async fn acquire(&self, tokens: u32) {
+        loop {
+            let wait: Duration = self.try_acquire(tokens, Instant::now());
+            if wait.is_zero() {
+                return;
+            }
+            PrintCommand::Issue.print_agent_message(
+                RATE_LIMIT_POSITION,
+                &format!("Waiting {:?} for the rate limit.", wait),
+            );
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // Take the request from the buckets when both allow it, otherwise return how long to wait
+    fn try_acquire(&self, tokens: u32, now: Instant) -> Duration {
+        let mut requests = self.requests.as_ref().map(|bucket| bucket.lock().unwrap());
+        let mut token_bucket = self.tokens.as_ref().map(|bucket| bucket.lock().unwrap());
+
+        let wait: Duration = requests
+            .as_mut()
+            .map_or(Duration::ZERO, |bucket| bucket.wait_for(1.0, now))
+            .max(
+                token_bucket
+                    .as_mut()
+                    .map_or(Duration::ZERO, |bucket| bucket.wait_for(tokens as f64, now)),
+            );
+        if wait.is_zero() {
+            if let Some(bucket) = requests.as_mut() {
+                bucket.take(1.0, now);
+            }
+            if let Some(bucket) = token_bucket.as_mut() {
+                bucket.take(tokens as f64, now);
+            }
+        }
+        return wait;
+    }
+
+    // Settle the difference between the announced and the actually used tokens
+    pub fn correct_tokens(&self, announced: u32, used: u32) {
+        if let Some(bucket) = &self.tokens {
+            bucket
+                .lock()
+                .unwrap()
+                .take(used as f64 - announced as f64, Instant::now());
+        }
+    }
+}
+
+// Provider wrapper which keeps the inner provider within the rate limits
+#[derive(Debug)]
+pub struct RateLimitedProvider {
+    inner: Arc<dyn LlmProvider>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitedProvider {
+    pub fn new(inner: Arc<dyn LlmProvider>, limiter: Arc<RateLimiter>) -> Self {
+        return Self { inner, limiter };
+    }
+}
+
+#[async_trait]
+impl LlmProvider for RateLimitedProvider {
+    async fn generate(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<LlmResponse, LlmError> {
+        let announced: u32 = estimate_tokens(message);
+        self.limiter.acquire(announced).await;
+        let response: LlmResponse = self.inner.generate(message, context).await?;
+        self.limiter
+            .correct_tokens(announced, response.usage.total_tokens());
+        return Ok(response);
+    }
+
+    async fn generate_stream(
+        &self,
+        message: &Message,
+        context: &CallContext,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<LlmResponse, LlmError> {
+        let announced: u32 = estimate_tokens(message);
+        self.limiter.acquire(announced).await;
+        let response: LlmResponse = self
+            .inner
+            .generate_stream(message, context, on_chunk)
+            .await?;
+        self.limiter
+            .correct_tokens(announced, response.usage.total_tokens());
+        return Ok(response);
+    }
+
+    // countTokens has its own quota, the limits only cover generating answers
+    async fn count_tokens(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<u32, LlmError> {
+        return self.inner.count_tokens(message, context).await;
+    }
+
+    // Uploading a context is no generateContent request either, its tokens are paid as storage
+    async fn create_cached_content(
+        &self,
+        prefix: &Message,
+        context: &CallContext,
+    ) -> Result<Option<CachedContent>, LlmError> {
+        return self.inner.create_cached_content(prefix, context).await;
+    }
+
+    fn usage_ledger(&self) -> Option<Arc<UsageLedger>> {
+        return self.inner.usage_ledger();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::general::llm::MessagePart;
+    use crate::test_support::scripted_provider::ScriptedProvider;
+
+    #[test]
+    fn test_token_bucket() {
+        let start: Instant = Instant::now();
+        let mut bucket: TokenBucket = TokenBucket::per_minute(15, start);
+
+        // The full bucket allows a burst of 15 requests
+        for _ in 0..15 {
+            assert_eq!(bucket.wait_for(1.0, start), Duration::ZERO);
+            bucket.take(1.0, start);
+        }
+        // Then one request every 4 seconds
+        assert_eq!(bucket.wait_for(1.0, start), Duration::from_secs(4));
+        assert_eq!(
+            bucket.wait_for(1.0, start + Duration::from_secs(4)),
+            Duration::ZERO
+        );
+
+        // Using more than announced puts the bucket in debt
+        let mut bucket: TokenBucket = TokenBucket::per_minute(600, start);
+        bucket.take(900.0, start);
+        assert_eq!(bucket.wait_for(1.0, start), Duration::from_secs_f64(30.1));
+        // Requests larger than the bucket wait for a full bucket instead of forever
+        assert_eq!(
+            bucket.wait_for(6000.0, start),
+            Duration::from_secs_f64(90.0)
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter_zero_is_unlimited() {
+        let limiter: RateLimiter = RateLimiter::new(&RateLimit {
+            requests_per_minute: Some(0),
+            tokens_per_minute: Some(0),
+        });
+        let now: Instant = Instant::now();
+        for _ in 0..100 {
+            assert_eq!(limiter.try_acquire(1_000_000, now), Duration::ZERO);
+        }
+        limiter.correct_tokens(1_000_000, 2_000_000);
+    }
+
+    #[test]
+    fn test_rate_limiter_queues() {
+        let limiter: RateLimiter = RateLimiter::new(&RateLimit {
+            requests_per_minute: Some(2),
+            tokens_per_minute: Some(1000),
+        });
+        let now: Instant = Instant::now();
+        assert_eq!(limiter.try_acquire(400, now), Duration::ZERO);
+        assert_eq!(limiter.try_acquire(400, now), Duration::ZERO);
+        // Out of requests, the wait is that of the slowest bucket
+        assert!(limiter.try_acquire(400, now) >= Duration::from_secs(29));
+
+        // The answers used more tokens than announced
+        limiter.correct_tokens(800, 2400);
+        assert!(limiter.try_acquire(1, now + Duration::from_secs(30)) >= Duration::from_secs(50));
+    }
+
+    #[tokio::test]
+    async fn test_counting_tokens_leaves_the_requests_alone() {
+        let limiter: Arc<RateLimiter> = Arc::new(RateLimiter::new(&RateLimit {
+            requests_per_minute: Some(1),
+            tokens_per_minute: None,
+        }));
+        let provider: RateLimitedProvider = RateLimitedProvider::new(
+            Arc::new(ScriptedProvider::new(vec![Ok("Hallo".to_string())])),
+            Arc::clone(&limiter),
+        );
+        let message: Message = Message {
+            contents: vec![MessagePart::user("Hello".to_string())],
+            ..Default::default()
+        };
+
+        for _ in 0..10 {
+            provider
+                .count_tokens(&message, &CallContext::default())
+                .await
+                .unwrap();
+        }
+        provider
+            .create_cached_content(&message, &CallContext::default())
+            .await
+            .unwrap();
+        // The only request of this minute is still available for the answer
+        assert_eq!(limiter.try_acquire(0, Instant::now()), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_unlimited() {
+        let limiter: RateLimiter = RateLimiter::new(&RateLimit::default());
+        for _ in 0..100 {
+            limiter.acquire(1_000_000).await;
+        }
+    }
+}
@@ -0,0 +1,374 @@
+#![allow(unused)]
+use crate::apis::llm_error::LlmError;
+use crate::apis::llm_provider::{
+    CachedContent, CallContext, ChunkCallback, LlmProvider, LlmResponse,
+};
+use crate::apis::usage::UsageLedger;
+use crate::helpers::command_line::PrintCommand;
+use crate::models::general::llm::Message;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const RETRY_POSITION: &str = "LLM retry";
+
+// How often and how patiently failed LLM calls are retried
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct RetryPolicy {
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_base_delay_ms")]
+    pub base_delay_ms: u64,
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+    // Fraction of the delay that is randomised, 0.0 disables jitter
+    #[serde(default = "default_jitter")]
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        return Self {
+            max_attempts: default_max_attempts(),
+            base_delay_ms: default_base_delay_ms(),
+            max_delay_ms: default_max_delay_ms(),
+            jitter: default_jitter(),
+        };
+    }
+}
+
+fn default_max_attempts() -> u32 {
+    return 3;
+}
+
+fn default_base_delay_ms() -> u64 {
+    return 1000;
+}
+
+fn default_max_delay_ms() -> u64 {
+    return 30_000;
+}
+
+fn default_jitter() -> f64 {
+    return 0.2;
+}
+
+impl RetryPolicy {
+    // Exponential backoff for the given (1 based) failed attempt, honoring Retry-After
+    pub fn delay_for(&self, attempt: u32, error: &LlmError) -> Duration {
+        if let Some(retry_after) = error.retry_after() {
+            return retry_after;
+        }
+
+        let exponential: u64 = self
+            .base_delay_ms
+            .saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1)));
+        let capped: f64 = exponential.min(self.max_delay_ms) as f64;
+
+        // Spread the delay over [capped * (1 - jitter), capped * (1 + jitter)]
+        let spread: f64 = capped * self.jitter * (2.0 * random_fraction() - 1.0);
+        return Duration::from_millis((capped + spread).max(0.0) as u64);
+    }
+}
+
+// Cheap source of randomness for jitter, good enough to spread out retries
+fn random_fraction() -> f64 {
+    let nanos: u32 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos())
+        .unwrap_or(0);
+    return (nanos % 1000) as f64 / 1000.0;
+}
+
+// Provider wrapper which retries transient errors of the inner provider
+#[derive(Debug)]
+pub struct RetryProvider {
+    inner: Arc<dyn LlmProvider>,
+    policy: RetryPolicy,
+}
+
+impl RetryProvider {
+    pub fn new(inner: Arc<dyn LlmProvider>, policy: RetryPolicy) -> Self {
+        return Self { inner, policy };
+    }
+
+    async fn with_retry<T, F, Fut>(&self, operation: F) -> Result<T, LlmError>
+    where
+        F: Fn() -> Fut + Send,
+        Fut: Future<Output = Result<T, LlmError>> + Send,
+    {
+        let mut history: Vec<String> = vec![];
+        let mut attempt: u32 = 1;
+        loop {
+            match operation().await {
+                Ok(result) => return Ok(result),
+                Err(error) => {
+                    if !self.should_retry(attempt, &error, &mut history).await {
+                        return Err(error);
+                    }
+                }
+            };
+            attempt += 1;
+        }
+    }
+
+    // Log the failed attempt and wait before the next one. Returns false when we should give up.
+    async fn should_retry(
+        &self,
+        attempt: u32,
+        error: &LlmError,
+        history: &mut Vec<String>,
+    ) -> bool {
+        history.push(format!("attempt {}: {}", attempt, error));
+
+        if !error.is_transient() || attempt >= self.policy.max_attempts {
+            if attempt > 1 {
+                PrintCommand::Issue.print_agent_message(
+                    RETRY_POSITION,
+                    &format!("Giving up after {}", history.join("; ")),
+                );
+            }
+            return false;
+        }
+
+        let delay: Duration = self.policy.delay_for(attempt, error);
+        PrintCommand::Issue.print_agent_message(
+            RETRY_POSITION,
+            &format!(
+                "Attempt {}/{} failed: {}. Retrying in {:?}.",
+                attempt, self.policy.max_attempts, error, delay
+            ),
+        );
+        tokio::time::sleep(delay).await;
+        return true;
+    }
+}
+
+#[async_trait]
+impl LlmProvider for RetryProvider {
+    async fn generate(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<LlmResponse, LlmError> {
+        return self
+            .with_retry(|| self.inner.generate(message, context))
+            .await;
+    }
+
+    async fn generate_structured(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<serde_json::Value, LlmError> {
+        return self
+            .with_retry(|| self.inner.generate_structured(message, context))
+            .await;
+    }
+
+    async fn generate_stream(
+        &self,
+        message: &Message,
+        context: &CallContext,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<LlmResponse, LlmError> {
+        // The callback can not be shared between attempts by with_retry, so loop here
+        let mut history: Vec<String> = vec![];
+        let mut attempt: u32 = 1;
+        loop {
+            let mut delivered: bool = false;
+            let result: Result<LlmResponse, LlmError> = {
+                let mut tracking_chunk = |chunk: &str| {
+                    delivered = true;
+                    on_chunk(chunk);
+                };
+                self.inner
+                    .generate_stream(message, context, &mut tracking_chunk)
+                    .await
+            };
+            match result {
+                Ok(result) => return Ok(result),
+                // A new attempt would repeat the text the caller already received
+                Err(error) if delivered => return Err(error),
+                Err(error) => {
+                    if !self.should_retry(attempt, &error, &mut history).await {
+                        return Err(error);
+                    }
+                }
+            };
+            attempt += 1;
+        }
+    }
+
+    async fn count_tokens(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<u32, LlmError> {
+        return self
+            .with_retry(|| self.inner.count_tokens(message, context))
+            .await;
+    }
+
+    async fn create_cached_content(
+        &self,
+        prefix: &Message,
+        context: &CallContext,
+    ) -> Result<Option<CachedContent>, LlmError> {
+        return self
+            .with_retry(|| self.inner.create_cached_content(prefix, context))
+            .await;
+    }
+
+    fn usage_ledger(&self) -> Option<Arc<UsageLedger>> {
+        return self.inner.usage_ledger();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::general::llm::{MessagePart, MessagePartText};
+    use crate::test_support::scripted_provider::ScriptedProvider;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Streams the first words of an answer, then loses the connection
+    #[derive(Debug, Default)]
+    struct BrokenStream {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl LlmProvider for BrokenStream {
+        async fn generate(
+            &self,
+            message: &Message,
+            context: &CallContext,
+        ) -> Result<LlmResponse, LlmError> {
+            return Err(LlmError::Network("connection reset".to_string()));
+        }
+
+        async fn generate_stream(
+            &self,
+            message: &Message,
+            context: &CallContext,
+            on_chunk: &mut ChunkCallback<'_>,
+        ) -> Result<LlmResponse, LlmError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            on_chunk("fn main() {");
+            return Err(LlmError::Network("connection reset".to_string()));
+        }
+
+        async fn count_tokens(
+            &self,
+            message: &Message,
+            context: &CallContext,
+        ) -> Result<u32, LlmError> {
+            return Ok(0);
+        }
+    }
+
+    fn instant_policy() -> RetryPolicy {
+        return RetryPolicy {
+            max_attempts: 3,
+            base_delay_ms: 0,
+            max_delay_ms: 0,
+            jitter: 0.0,
+        };
+    }
+
+    fn message() -> Message {
+        return Message {
+            contents: vec![MessagePart::user("Hello".to_string())],
+            ..Default::default()
+        };
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient_errors() {
+        let inner: Arc<ScriptedProvider> = Arc::new(ScriptedProvider::new(vec![
+            Err(LlmError::Network("connection reset".to_string())),
+            Err(LlmError::RateLimited { retry_after: None }),
+            Ok("Hallo".to_string()),
+        ]));
+        let provider: RetryProvider = RetryProvider::new(inner.clone(), instant_policy());
+
+        let response: LlmResponse = provider
+            .generate(&message(), &CallContext::default())
+            .await
+            .unwrap();
+        assert_eq!(response.text, "Hallo");
+        assert_eq!(inner.calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_for_permanent_errors() {
+        let inner: Arc<ScriptedProvider> =
+            Arc::new(ScriptedProvider::new(vec![Err(LlmError::Http {
+                status: 400,
+                body: "bad request".to_string(),
+            })]));
+        let provider: RetryProvider = RetryProvider::new(inner.clone(), instant_policy());
+
+        assert!(
+            provider
+                .generate(&message(), &CallContext::default())
+                .await
+                .is_err()
+        );
+        assert_eq!(inner.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_stream_only_before_the_first_chunk() {
+        let inner: Arc<ScriptedProvider> = Arc::new(ScriptedProvider::new(vec![
+            Err(LlmError::Network("connection reset".to_string())),
+            Ok("Hallo".to_string()),
+        ]));
+        let provider: RetryProvider = RetryProvider::new(inner.clone(), instant_policy());
+        let mut chunks: Vec<String> = vec![];
+        let response: LlmResponse = provider
+            .generate_stream(&message(), &CallContext::default(), &mut |chunk: &str| {
+                chunks.push(chunk.to_string())
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.text, "Hallo");
+        assert_eq!(chunks, vec!["Hallo"]);
+        assert_eq!(inner.calls(), 2);
+
+        // Text already printed is not streamed a second time
+        let inner: Arc<BrokenStream> = Arc::new(BrokenStream::default());
+        let provider: RetryProvider = RetryProvider::new(inner.clone(), instant_policy());
+        let mut chunks: Vec<String> = vec![];
+        let result: Result<LlmResponse, LlmError> = provider
+            .generate_stream(&message(), &CallContext::default(), &mut |chunk: &str| {
+                chunks.push(chunk.to_string())
+            })
+            .await;
+        assert!(matches!(result, Err(LlmError::Network(_))));
+        assert_eq!(chunks, vec!["fn main() {"]);
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_delay_for() {
+        let policy: RetryPolicy = RetryPolicy {
+            max_attempts: 5,
+            base_delay_ms: 100,
+            max_delay_ms: 300,
+            jitter: 0.0,
+        };
+        let error: LlmError = LlmError::Network("timeout".to_string());
+        assert_eq!(policy.delay_for(1, &error), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(2, &error), Duration::from_millis(200));
+        assert_eq!(policy.delay_for(3, &error), Duration::from_millis(300));
+
+        let error: LlmError = LlmError::RateLimited {
+            retry_after: Some(Duration::from_secs(2)),
+        };
+        assert_eq!(policy.delay_for(1, &error), Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,293 @@
+#![allow(unused)]
+use crate::apis::llm_error::LlmError;
+use crate::apis::llm_provider::{
+    CachedContent, CallContext, ChunkCallback, LlmProvider, LlmResponse, TokenUsage,
+};
+use crate::models::general::llm::Message;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+// Price of a model in US dollars per million tokens
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+impl ModelPrice {
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        return (usage.prompt_tokens as f64 * self.input_per_million
+            + usage.completion_tokens as f64 * self.output_per_million)
+            / 1_000_000.0;
+    }
+}
+
+// Paid tier prices of the Gemini models, local models are free
+pub fn default_pricing() -> BTreeMap<String, ModelPrice> {
+    let mut pricing: BTreeMap<String, ModelPrice> = BTreeMap::new();
+    for (model, input_per_million, output_per_million) in [
+        ("gemini-2.0-flash", 0.10, 0.40),
+        ("gemini-2.0-flash-lite", 0.075, 0.30),
+        ("gemini-2.5-flash", 0.30, 2.50),
+        ("gemini-2.5-pro", 1.25, 10.00),
+    ] {
+        pricing.insert(
+            model.to_string(),
+            ModelPrice {
+                input_per_million,
+                output_per_million,
+            },
+        );
+    }
+    return pricing;
+}
+
+// Usage of one successful call
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageRecord {
+    pub context: CallContext,
+    pub model: String,
+    pub usage: TokenUsage,
+}
+
+// Totals of a group of calls
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct UsageTotals {
+    pub calls: u32,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub cost: f64,
+}
+
+impl UsageTotals {
+    pub fn total_tokens(&self) -> u32 {
+        return self.prompt_tokens + self.completion_tokens;
+    }
+
+    fn add(&mut self, usage: &TokenUsage, cost: f64) {
+        self.calls += 1;
+        self.prompt_tokens += usage.prompt_tokens;
+        self.completion_tokens += usage.completion_tokens;
+        self.cost += cost;
+    }
+}
+
+// Collects the usage of every call during a run
+#[derive(Debug)]
+pub struct UsageLedger {
+    pricing: BTreeMap<String, ModelPrice>,
+    records: Mutex<Vec<UsageRecord>>,
+}
+
+impl UsageLedger {
+    pub fn new(pricing: BTreeMap<String, ModelPrice>) -> Self {
+        return Self {
+            pricing,
+            records: Mutex::new(vec![]),
+        };
+    }
+
+    pub fn record(&self, context: &CallContext, response: &LlmResponse) {
+        self.records.lock().unwrap().push(UsageRecord {
+            context: context.clone(),
+            model: response.model.clone(),
+            usage: response.usage,
+        });
+    }
+
+    pub fn records(&self) -> Vec<UsageRecord> {
+        return self.records.lock().unwrap().clone();
+    }
+
+    // Estimated cost of a call, models without a price are free
+    pub fn cost(&self, record: &UsageRecord) -> f64 {
+        return self
+            .pricing
+            .get(&record.model)
+            .map_or(0.0, |price| price.cost(&record.usage));
+    }
+
+    pub fn totals(&self) -> UsageTotals {
+        let mut totals: UsageTotals = UsageTotals::default();
+        for record in self.records() {
+            totals.add(&record.usage, self.cost(&record));
+        }
+        return totals;
+    }
+
+    // Totals per agent and AI function
+    pub fn totals_per_call_site(&self) -> BTreeMap<CallContext, UsageTotals> {
+        let mut totals: BTreeMap<CallContext, UsageTotals> = BTreeMap::new();
+        for record in self.records() {
+            let cost: f64 = self.cost(&record);
+            totals
+                .entry(record.context.clone())
+                .or_default()
+                .add(&record.usage, cost);
+        }
+        return totals;
+    }
+
+    // Models which were used but have no price in the config
+    pub fn unpriced_models(&self) -> Vec<String> {
+        let mut models: Vec<String> = self
+            .records()
+            .into_iter()
+            .map(|record| record.model)
+            .filter(|model| !self.pricing.contains_key(model))
+            .collect();
+        models.sort();
+        models.dedup();
+        return models;
+    }
+
+    // Human readable report of the run
+    pub fn report(&self) -> String {
+        let mut report: String = format!(
+            "{:<22} {:<32} {:>5} {:>10} {:>10} {:>10}\n",
+            "Agent", "AI function", "Calls", "Prompt", "Output", "Cost ($)"
+        );
+        for (context, totals) in self.totals_per_call_site() {
+            report.push_str(&format!(
+                "{:<22} {:<32} {:>5} {:>10} {:>10} {:>10.4}\n",
+                context.agent,
+                context.function,
+                totals.calls,
+                totals.prompt_tokens,
+                totals.completion_tokens,
+                totals.cost
+            ));
+        }
+        let totals: UsageTotals = self.totals();
+        report.push_str(&format!(
+            "{:<22} {:<32} {:>5} {:>10} {:>10} {:>10.4}",
+            "Total", "", totals.calls, totals.prompt_tokens, totals.completion_tokens, totals.cost
+        ));
+        let unpriced: Vec<String> = self.unpriced_models();
+        if !unpriced.is_empty() {
+            report.push_str(&format!(
+                "\nNo price configured for: {}",
+                unpriced.join(", ")
+            ));
+        }
+        return report;
+    }
+}
+
+// Provider wrapper which writes the usage of every answer to the ledger
+#[derive(Debug)]
+pub struct MeteredProvider {
+    inner: Arc<dyn LlmProvider>,
+    ledger: Arc<UsageLedger>,
+}
+
+impl MeteredProvider {
+    pub fn new(inner: Arc<dyn LlmProvider>, ledger: Arc<UsageLedger>) -> Self {
+        return Self { inner, ledger };
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MeteredProvider {
+    async fn generate(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<LlmResponse, LlmError> {
+        let response: LlmResponse = self.inner.generate(message, context).await?;
+        self.ledger.record(context, &response);
+        return Ok(response);
+    }
+
+    async fn generate_stream(
+        &self,
+        message: &Message,
+        context: &CallContext,
+        on_chunk: &mut ChunkCallback<'_>,
+    ) -> Result<LlmResponse, LlmError> {
+        let response: LlmResponse = self
+            .inner
+            .generate_stream(message, context, on_chunk)
+            .await?;
+        self.ledger.record(context, &response);
+        return Ok(response);
+    }
+
+    async fn count_tokens(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<u32, LlmError> {
+        return self.inner.count_tokens(message, context).await;
+    }
+
+    async fn create_cached_content(
+        &self,
+        prefix: &Message,
+        context: &CallContext,
+    ) -> Result<Option<CachedContent>, LlmError> {
+        return self.inner.create_cached_content(prefix, context).await;
+    }
+
+    fn usage_ledger(&self) -> Option<Arc<UsageLedger>> {
+        return Some(Arc::clone(&self.ledger));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::general::llm::{MessagePart, MessagePartText};
+    use crate::test_support::scripted_provider::ScriptedProvider;
+
+    #[tokio::test]
+    async fn test_metered_provider_report() {
+        let inner: Arc<ScriptedProvider> = Arc::new(ScriptedProvider::new(vec![
+            Ok("a".repeat(400)),
+            Ok("b".repeat(40)),
+            Ok("c".repeat(4)),
+        ]));
+        let provider: MeteredProvider =
+            MeteredProvider::new(inner, Arc::new(UsageLedger::new(default_pricing())));
+        let message: Message = Message {
+            contents: vec![MessagePart::user("x".repeat(4000))],
+            ..Default::default()
+        };
+
+        let architect: CallContext = CallContext::new("Solutions architect", "print_site_urls");
+        let manager: CallContext =
+            CallContext::new("Project Manager", "convert_user_input_to_goal");
+        provider.generate(&message, &architect).await.unwrap();
+        provider.generate(&message, &architect).await.unwrap();
+        provider.generate(&message, &manager).await.unwrap();
+
+        let ledger: Arc<UsageLedger> = provider.usage_ledger().unwrap();
+        let per_call_site: BTreeMap<CallContext, UsageTotals> = ledger.totals_per_call_site();
+        assert_eq!(per_call_site[&architect].calls, 2);
+        assert_eq!(per_call_site[&architect].prompt_tokens, 2000);
+        assert_eq!(per_call_site[&architect].completion_tokens, 110);
+        assert_eq!(per_call_site[&manager].calls, 1);
+
+        // ScriptedProvider answers as "scripted", which has no price
+        let totals: UsageTotals = ledger.totals();
+        assert_eq!(totals.total_tokens(), 3111);
+        assert_eq!(totals.cost, 0.0);
+        assert!(
+            ledger
+                .report()
+                .contains("No price configured for: scripted")
+        );
+    }
+
+    #[test]
+    fn test_model_price_cost() {
+        let price: ModelPrice = default_pricing()["gemini-2.0-flash"];
+        let usage: TokenUsage = TokenUsage {
+            prompt_tokens: 1_000_000,
+            completion_tokens: 500_000,
+        };
+        assert!((price.cost(&usage) - 0.30).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,108 @@
+#![allow(unused)]
+use crate::models::general::llm::InlineData;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use std::path::Path;
+
+// Gemini refuses requests above 20 MB, the base64 encoding adds a third. All attachments go in
+// the same request, so this is the limit for all of them together.
+const MAX_ATTACHMENT_BYTES: usize = 14 * 1024 * 1024;
+
+// MIME type of the file types Gemini understands, recognised by their extension
+fn mime_type_for(path: &Path) -> Option<&'static str> {
+    let extension: String = path.extension()?.to_str()?.to_lowercase();
+    return match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "webp" => Some("image/webp"),
+        "heic" => Some("image/heic"),
+        "heif" => Some("image/heif"),
+        "pdf" => Some("application/pdf"),
+        "txt" => Some("text/plain"),
+        "md" => Some("text/md"),
+        "html" => Some("text/html"),
+        "csv" => Some("text/csv"),
+        _ => None,
+    };
+}
+
+// Read a file to send along with a prompt
+pub fn load_attachment(path: &str) -> Result<InlineData, String> {
+    let mime_type: &str = mime_type_for(Path::new(path))
+        .ok_or(format!("{} is not an image, PDF or text file", path))?;
+    let bytes: Vec<u8> =
+        std::fs::read(path).map_err(|error| format!("Failed to read {}: {}", path, error))?;
+    if bytes.len() > MAX_ATTACHMENT_BYTES {
+        return Err(format!(
+            "{} is larger than {} MB",
+            path,
+            MAX_ATTACHMENT_BYTES / 1024 / 1024
+        ));
+    }
+    return Ok(InlineData {
+        mime_type: mime_type.to_string(),
+        data: STANDARD.encode(bytes),
+    });
+}
+
+// Read the files to send along with a prompt, refusing them before reading when the request
+// would get too large
+pub fn load_attachments(paths: &[String]) -> Result<Vec<InlineData>, String> {
+    let mut total_bytes: u64 = 0;
+    for path in paths {
+        mime_type_for(Path::new(path))
+            .ok_or(format!("{} is not an image, PDF or text file", path))?;
+        total_bytes += std::fs::metadata(path)
+            .map_err(|error| format!("Failed to read {}: {}", path, error))?
+            .len();
+    }
+    if total_bytes > MAX_ATTACHMENT_BYTES as u64 {
+        return Err(format!(
+            "The attachments are {} MB together, a request allows {} MB",
+            total_bytes.div_ceil(1024 * 1024),
+            MAX_ATTACHMENT_BYTES / 1024 / 1024
+        ));
+    }
+    return paths.iter().map(|path| load_attachment(path)).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_attachment() {
+        let path: std::path::PathBuf = std::env::temp_dir().join("wireframe.PNG");
+        std::fs::write(&path, b"\x89PNG").unwrap();
+        let attachment: InlineData = load_attachment(path.to_str().unwrap()).unwrap();
+        assert_eq!(attachment.mime_type, "image/png");
+        assert_eq!(attachment.data, "iVBORw==");
+
+        assert_eq!(
+            load_attachment("spec.docx"),
+            Err("spec.docx is not an image, PDF or text file".to_string())
+        );
+    }
+
+    #[test]
+    fn test_load_attachments_total_size() {
+        let paths: Vec<String> = ["attachment_page_1.pdf", "attachment_page_2.pdf"]
+            .iter()
+            .map(|name| {
+                let path: std::path::PathBuf = std::env::temp_dir().join(name);
+                // Sparse files, only the size matters
+                std::fs::File::create(&path)
+                    .unwrap()
+                    .set_len(8 * 1024 * 1024)
+                    .unwrap();
+                return path.to_str().unwrap().to_string();
+            })
+            .collect();
+
+        assert_eq!(load_attachments(&paths[..1]).unwrap().len(), 1);
+        assert_eq!(
+            load_attachments(&paths),
+            Err("The attachments are 16 MB together, a request allows 14 MB".to_string())
+        );
+    }
+}
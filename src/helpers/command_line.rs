@@ -32,6 +32,21 @@ impl PrintCommand {
     }
 }
 
+// Echo a piece of a streamed LLM answer as soon as it arrives
+pub fn print_stream_chunk(chunk: &str) {
+    let mut stdout: std::io::Stdout = stdout();
+
+    stdout.execute(SetForegroundColor(Color::DarkGrey)).unwrap();
+    print!("{}", chunk);
+    stdout.execute(ResetColor).unwrap();
+    stdout.flush().unwrap();
+}
+
+// End a streamed LLM answer on its own line
+pub fn finish_stream() {
+    println!();
+}
+
 // Get user request
 pub fn get_user_reponse(question: &str) -> String {
     let mut stdout: std::io::Stdout = stdout();
@@ -73,9 +88,13 @@ pub fn confirm_safe_code() -> bool {
         stdout.flush().unwrap(); // ✅ Ensures prompt appears before input
 
         let mut user_input: String = String::new();
-        stdin
+        let read: usize = stdin
             .read_line(&mut user_input)
             .expect("Failed to read input from stdin!");
+        // Nobody can confirm when stdin is closed (e.g. in CI), so do not run the code
+        if read == 0 {
+            return false;
+        }
         let trimmed_user_input = user_input.trim().to_lowercase();
         match trimmed_user_input.as_str() {
             "1" | "ok" | "y" | "yes" => return true,
@@ -0,0 +1,319 @@
+#![allow(unused)]
+use crate::ai_functions::prompts::PromptConfig;
+use crate::apis::budget::Budget;
+use crate::apis::cache::CacheConfig;
+use crate::apis::rate_limit::RateLimit;
+use crate::apis::retry::RetryPolicy;
+use crate::apis::usage::{ModelPrice, default_pricing};
+use crate::helpers::secret::ApiKey;
+use crate::models::agent_basic::memory::MemoryConfig;
+use dotenv::dotenv;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+
+const DEFAULT_CONFIG_PATH: &str = "autogemini.toml";
+
+// Backends which can serve the agents
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    #[default]
+    Gemini,
+    OpenAi,
+    Ollama,
+}
+
+// Configuration of a run, read from autogemini.toml with overrides from the environment
+#[derive(Debug, Deserialize, Clone)]
+pub struct AppConfig {
+    #[serde(default)]
+    pub provider: ProviderKind,
+    #[serde(default)]
+    pub gemini: GeminiConfig,
+    #[serde(default)]
+    pub openai: OpenAiConfig,
+    #[serde(default)]
+    pub ollama: OllamaConfig,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+    #[serde(default)]
+    pub budget: Budget,
+    #[serde(default)]
+    pub memory: MemoryConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub prompts: PromptConfig,
+    // Price per model for the usage report, replaces the built-in table when given
+    #[serde(default = "default_pricing")]
+    pub pricing: BTreeMap<String, ModelPrice>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        return Self {
+            provider: ProviderKind::default(),
+            gemini: GeminiConfig::default(),
+            openai: OpenAiConfig::default(),
+            ollama: OllamaConfig::default(),
+            retry: RetryPolicy::default(),
+            rate_limit: RateLimit::default(),
+            budget: Budget::default(),
+            memory: MemoryConfig::default(),
+            cache: CacheConfig::default(),
+            prompts: PromptConfig::default(),
+            pricing: default_pricing(),
+        };
+    }
+}
+
+// Settings for the Gemini API, the base url can point at a proxy or a mock server
+#[derive(Debug, Deserialize, Clone)]
+pub struct GeminiConfig {
+    #[serde(default = "default_gemini_base_url")]
+    pub base_url: String,
+    #[serde(default = "default_gemini_model")]
+    pub model: String,
+    // Model per AI function (e.g. print_fixed_code = "gemini-2.5-pro"), others use `model`
+    #[serde(default)]
+    pub models: BTreeMap<String, String>,
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    // Limit for a whole request, including a streamed answer
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    // How long a context shared by the calls of an agent stays uploaded
+    #[serde(default = "default_cached_content_ttl_secs")]
+    pub cached_content_ttl_secs: u64,
+    // e.g. "http://proxy.local:3128", by default the HTTPS_PROXY variable is honored
+    #[serde(default)]
+    pub proxy: Option<String>,
+    // Secret, never read from the config file but from GEMINI_API_KEY
+    #[serde(skip)]
+    pub api_key: Option<ApiKey>,
+}
+
+impl Default for GeminiConfig {
+    fn default() -> Self {
+        return Self {
+            base_url: default_gemini_base_url(),
+            model: default_gemini_model(),
+            models: BTreeMap::new(),
+            connect_timeout_secs: default_connect_timeout_secs(),
+            request_timeout_secs: default_request_timeout_secs(),
+            cached_content_ttl_secs: default_cached_content_ttl_secs(),
+            proxy: None,
+            api_key: None,
+        };
+    }
+}
+
+// Settings for an OpenAI compatible chat completions server (vLLM, llama.cpp, ...)
+#[derive(Debug, Deserialize, Clone)]
+pub struct OpenAiConfig {
+    #[serde(default = "default_openai_base_url")]
+    pub base_url: String,
+    #[serde(default = "default_openai_model")]
+    pub model: String,
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    // Secret, never read from the config file but from OPENAI_API_KEY. Local servers usually do
+    // not need one.
+    #[serde(skip)]
+    pub api_key: Option<ApiKey>,
+}
+
+impl Default for OpenAiConfig {
+    fn default() -> Self {
+        return Self {
+            base_url: default_openai_base_url(),
+            model: default_openai_model(),
+            connect_timeout_secs: default_connect_timeout_secs(),
+            request_timeout_secs: default_request_timeout_secs(),
+            api_key: None,
+        };
+    }
+}
+
+// Settings for a local Ollama server
+#[derive(Debug, Deserialize, Clone)]
+pub struct OllamaConfig {
+    #[serde(default = "default_ollama_base_url")]
+    pub base_url: String,
+    #[serde(default = "default_ollama_model")]
+    pub model: String,
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    // Limit for a whole request, a local model on a CPU can take minutes
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+}
+
+impl Default for OllamaConfig {
+    fn default() -> Self {
+        return Self {
+            base_url: default_ollama_base_url(),
+            model: default_ollama_model(),
+            connect_timeout_secs: default_connect_timeout_secs(),
+            request_timeout_secs: default_request_timeout_secs(),
+        };
+    }
+}
+
+fn default_gemini_base_url() -> String {
+    return "https://generativelanguage.googleapis.com/v1beta".to_string();
+}
+
+fn default_gemini_model() -> String {
+    return "gemini-2.0-flash".to_string();
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    return 10;
+}
+
+fn default_request_timeout_secs() -> u64 {
+    return 300;
+}
+
+fn default_cached_content_ttl_secs() -> u64 {
+    return 3600;
+}
+
+fn default_openai_base_url() -> String {
+    return "http://localhost:8000/v1".to_string();
+}
+
+fn default_openai_model() -> String {
+    return "default".to_string();
+}
+
+fn default_ollama_base_url() -> String {
+    return "http://localhost:11434".to_string();
+}
+
+fn default_ollama_model() -> String {
+    return "llama3.2".to_string();
+}
+
+impl AppConfig {
+    // Load the config file (AUTOGEMINI_CONFIG or ./autogemini.toml). A missing file gives the defaults.
+    pub fn load() -> Self {
+        dotenv().ok();
+
+        let config_path: String =
+            env::var("AUTOGEMINI_CONFIG").unwrap_or(DEFAULT_CONFIG_PATH.to_string());
+        let mut config: AppConfig = match std::fs::read_to_string(&config_path) {
+            Ok(contents) => Self::from_toml(&contents)
+                .unwrap_or_else(|e| panic!("Failed to parse config file {}: {}", config_path, e)),
+            Err(_) => AppConfig::default(),
+        };
+
+        // Environment variables win over the config file
+        if let Ok(provider) = env::var("LLM_PROVIDER") {
+            config.provider = match provider.to_lowercase().as_str() {
+                "gemini" => ProviderKind::Gemini,
+                "openai" => ProviderKind::OpenAi,
+                "ollama" => ProviderKind::Ollama,
+                _ => panic!("Unknown LLM_PROVIDER: {}", provider),
+            };
+        }
+        config.gemini.api_key = env::var("GEMINI_API_KEY")
+            .ok()
+            .map(|api_key| ApiKey::new(&api_key));
+        if let Ok(base_url) = env::var("GEMINI_BASE_URL") {
+            config.gemini.base_url = base_url;
+        }
+        if let Ok(model) = env::var("GEMINI_MODEL") {
+            config.gemini.model = model;
+        }
+        config.openai.api_key = env::var("OPENAI_API_KEY")
+            .ok()
+            .map(|api_key| ApiKey::new(&api_key));
+        if let Ok(base_url) = env::var("OPENAI_BASE_URL") {
+            config.openai.base_url = base_url;
+        }
+        if let Ok(model) = env::var("OPENAI_MODEL") {
+            config.openai.model = model;
+        }
+        if let Ok(base_url) = env::var("OLLAMA_BASE_URL") {
+            config.ollama.base_url = base_url;
+        }
+        if let Ok(model) = env::var("OLLAMA_MODEL") {
+            config.ollama.model = model;
+        }
+        if env::var("LLM_CACHE_BYPASS").is_ok_and(|bypass| bypass == "1") {
+            config.cache.bypass = true;
+        }
+
+        return config;
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, toml::de::Error> {
+        return toml::from_str(contents);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_from_toml() {
+        let config: AppConfig = AppConfig::from_toml(
+            r#"
+            provider = "openai"
+
+            [openai]
+            base_url = "http://127.0.0.1:8080/v1"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.provider, ProviderKind::OpenAi);
+        assert_eq!(config.openai.base_url, "http://127.0.0.1:8080/v1");
+        assert_eq!(config.openai.model, "default");
+        assert_eq!(config.ollama.base_url, "http://localhost:11434");
+        assert_eq!(config.ollama.request_timeout_secs, 300);
+        assert_eq!(
+            config.gemini.base_url,
+            "https://generativelanguage.googleapis.com/v1beta"
+        );
+        assert_eq!(config.gemini.model, "gemini-2.0-flash");
+        assert_eq!(config.gemini.request_timeout_secs, 300);
+        assert_eq!(config.gemini.api_key, None);
+        assert_eq!(config.retry, RetryPolicy::default());
+        assert_eq!(config.budget, Budget::default());
+        assert_eq!(config.memory, MemoryConfig::default());
+        assert_eq!(config.cache, CacheConfig::default());
+        assert_eq!(config.prompts.directory, None);
+        assert_eq!(config.rate_limit, RateLimit::default());
+        assert_eq!(config.pricing, default_pricing());
+
+        let config: AppConfig = AppConfig::from_toml(
+            r#"
+            [budget]
+            max_calls = 40
+            max_cost = 0.5
+
+            [gemini.models]
+            print_fixed_code = "gemini-2.5-pro"
+
+            [pricing."llama3.2"]
+            input_per_million = 0.0
+            output_per_million = 0.0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.provider, ProviderKind::Gemini);
+        assert_eq!(config.pricing.len(), 1);
+        assert_eq!(config.budget.max_calls, Some(40));
+        assert_eq!(config.budget.max_tokens, None);
+        assert_eq!(config.budget.max_cost, Some(0.5));
+        assert_eq!(config.gemini.models["print_fixed_code"], "gemini-2.5-pro");
+    }
+}
@@ -0,0 +1,128 @@
+use serde::de::DeserializeOwned;
+
+// Cut the JSON out of a model answer: drops code fences and prose before or after it
+pub fn extract_json(text: &str) -> &str {
+    let start: Option<usize> = text.find(['{', '[']);
+    let end: Option<usize> = text.rfind(['}', ']']);
+    return match (start, end) {
+        (Some(start), Some(end)) if start < end => &text[start..=end],
+        _ => text.trim(),
+    };
+}
+
+// Remove the things models like to add to JSON: // comments and trailing commas
+pub fn lenient_json(text: &str) -> String {
+    return remove_trailing_commas(&remove_line_comments(text));
+}
+
+fn remove_line_comments(text: &str) -> String {
+    let mut cleaned: String = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string: bool = false;
+    let mut escaped: bool = false;
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => (),
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == '/' && chars.peek() == Some(&'/') {
+            // Skip until the end of the line
+            while chars.peek().is_some_and(|next| *next != '\n') {
+                chars.next();
+            }
+            continue;
+        }
+        cleaned.push(c);
+    }
+    return cleaned;
+}
+
+fn remove_trailing_commas(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut cleaned: String = String::with_capacity(text.len());
+    let mut in_string: bool = false;
+    let mut escaped: bool = false;
+
+    for (index, c) in chars.iter().enumerate() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => (),
+            }
+        } else if *c == '"' {
+            in_string = true;
+        } else if *c == ',' {
+            // Drop the comma when the next meaningful character closes the object or array
+            let next: Option<&char> = chars[index + 1..].iter().find(|c| !c.is_whitespace());
+            if matches!(next, Some('}') | Some(']')) {
+                continue;
+            }
+        }
+        cleaned.push(*c);
+    }
+    return cleaned;
+}
+
+// Decode a model answer, first as is and then after the lenient clean up
+pub fn decode_llm_json<T: DeserializeOwned>(text: &str) -> Result<T, serde_json::Error> {
+    let json: &str = extract_json(text);
+    return match serde_json::from_str::<T>(json) {
+        Ok(decoded) => Ok(decoded),
+        Err(error) => serde_json::from_str::<T>(&lenient_json(json)).map_err(|_| error),
+    };
+}
+
+// Instruction asking the model to correct output which failed to decode
+pub fn repair_instruction(bad_output: &str, error: &serde_json::Error) -> String {
+    return format!(
+        "Your previous output could not be parsed as JSON.
+        PARSE ERROR: {}
+        PREVIOUS OUTPUT: {}
+        Print ONLY the corrected JSON. No commentary and no code blocks.",
+        error, bad_output
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::agents::agent_traits::ProjectScope;
+
+    #[test]
+    fn test_decode_llm_json() {
+        let answer: &str = r#"Sure! Here is the scope:
+```json
+{
+    "is_crud_required": true, // true if site needs CRUD functionality
+    "is_user_login_and_logout": false,
+    "is_external_urls_required": true,
+}
+```"#;
+        let scope: ProjectScope = decode_llm_json(answer).unwrap();
+        assert!(scope.is_crud_required);
+        assert!(!scope.is_user_login_and_logout);
+        assert!(scope.is_external_urls_required);
+
+        let urls: Vec<String> =
+            decode_llm_json(r#"["https://api.binance.com/api/v3/exchangeInfo",]"#).unwrap();
+        assert_eq!(urls, vec!["https://api.binance.com/api/v3/exchangeInfo"]);
+
+        assert!(decode_llm_json::<ProjectScope>("I can not help with that").is_err());
+    }
+
+    #[test]
+    fn test_lenient_json_keeps_strings() {
+        assert_eq!(
+            lenient_json(r#"{"url": "https://example.com", "note": "a, ]"}"#),
+            r#"{"url": "https://example.com", "note": "a, ]"}"#
+        );
+    }
+}
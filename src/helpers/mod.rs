@@ -1,2 +1,7 @@
+pub mod attachments;
 pub mod command_line;
+pub mod config;
 pub mod general;
+pub mod json_repair;
+pub mod secret;
+pub mod tools;
@@ -0,0 +1,56 @@
+#![allow(unused)]
+use std::fmt;
+
+const REDACTED: &str = "[REDACTED]";
+
+// An API key which never shows up in debug prints, error messages or logs.
+// Only `expose` gives the key, for the request header.
+#[derive(Clone, PartialEq)]
+pub struct ApiKey(String);
+
+impl ApiKey {
+    pub fn new(key: &str) -> Self {
+        return Self(key.to_string());
+    }
+
+    pub fn expose(&self) -> &str {
+        return &self.0;
+    }
+
+    // Replace every occurrence of the key in a text
+    pub fn redact(&self, text: &str) -> String {
+        if self.0.is_empty() {
+            return text.to_string();
+        }
+        return text.replace(&self.0, REDACTED);
+    }
+}
+
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ApiKey({})", REDACTED)
+    }
+}
+
+impl fmt::Display for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_key_is_redacted() {
+        let key: ApiKey = ApiKey::new("AIzaSecret123");
+        assert_eq!(format!("{:?}", key), "ApiKey([REDACTED])");
+        assert_eq!(format!("{}", key), "[REDACTED]");
+        assert_eq!(
+            key.redact("https://host/models/m:generateContent?key=AIzaSecret123"),
+            "https://host/models/m:generateContent?key=[REDACTED]"
+        );
+        assert_eq!(key.expose(), "AIzaSecret123");
+    }
+}
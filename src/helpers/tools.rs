@@ -0,0 +1,284 @@
+#![allow(unused)]
+use crate::helpers::general::check_status_code;
+use crate::models::general::llm::{FunctionCall, FunctionDeclaration, FunctionResponse, Tool};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+// A local function the model may call during a turn
+#[async_trait]
+pub trait AgentTool: Send + Sync {
+    // Name, purpose and parameters as declared to the model
+    fn declaration(&self) -> FunctionDeclaration;
+
+    // Run the tool with the arguments chosen by the model. The error is reported back to the
+    // model, which may try again.
+    async fn call(&self, args: &Value) -> Result<Value, String>;
+}
+
+// The tools an agent offers the model
+#[derive(Default)]
+pub struct ToolBox {
+    tools: Vec<Box<dyn AgentTool>>,
+}
+
+impl fmt::Debug for ToolBox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = self
+            .tools
+            .iter()
+            .map(|tool| tool.declaration().name)
+            .collect();
+        f.debug_tuple("ToolBox").field(&names).finish()
+    }
+}
+
+impl ToolBox {
+    pub fn new() -> Self {
+        return Self { tools: vec![] };
+    }
+
+    pub fn with(mut self, tool: impl AgentTool + 'static) -> Self {
+        self.tools.push(Box::new(tool));
+        return self;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.tools.is_empty();
+    }
+
+    // The tools field of a request
+    pub fn declarations(&self) -> Vec<Tool> {
+        if self.tools.is_empty() {
+            return vec![];
+        }
+        return vec![Tool {
+            function_declarations: self.tools.iter().map(|tool| tool.declaration()).collect(),
+        }];
+    }
+
+    // Run the tool the model asked for, failures become an error the model can read
+    pub async fn dispatch(&self, call: &FunctionCall) -> FunctionResponse {
+        let tool: Option<&Box<dyn AgentTool>> = self
+            .tools
+            .iter()
+            .find(|tool| tool.declaration().name == call.name);
+        let result: Result<Value, String> = match tool {
+            Some(tool) => tool.call(&call.args).await,
+            None => Err(format!("There is no tool named {}", call.name)),
+        };
+        return FunctionResponse {
+            name: call.name.clone(),
+            response: match result {
+                Ok(output) => json!({ "output": output }),
+                Err(error) => json!({ "error": error }),
+            },
+        };
+    }
+}
+
+// A string argument of a function call
+fn string_arg<'a>(args: &'a Value, name: &str) -> Result<&'a str, String> {
+    return args[name]
+        .as_str()
+        .ok_or(format!("Missing string argument {}", name));
+}
+
+// Reads a file of the project, paths outside the project are refused
+pub struct ReadFileTool {
+    root: PathBuf,
+}
+
+impl ReadFileTool {
+    pub fn new(root: &str) -> Self {
+        return Self {
+            root: PathBuf::from(root),
+        };
+    }
+}
+
+#[async_trait]
+impl AgentTool for ReadFileTool {
+    fn declaration(&self) -> FunctionDeclaration {
+        return FunctionDeclaration {
+            name: "read_file".to_string(),
+            description: "Read a file of the web server project".to_string(),
+            parameters: Some(json!({
+                "type": "OBJECT",
+                "properties": {
+                    "path": { "type": "STRING", "description": "Path relative to the project, e.g. src/main.rs" }
+                },
+                "required": ["path"]
+            })),
+        };
+    }
+
+    async fn call(&self, args: &Value) -> Result<Value, String> {
+        let path: &Path = Path::new(string_arg(args, "path")?);
+        if !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(format!("{} is outside the project", path.display()));
+        }
+        // A symbolic link inside the project may still point outside of it
+        let read_error =
+            |error: std::io::Error| format!("Failed to read {}: {}", path.display(), error);
+        let root: PathBuf = tokio::fs::canonicalize(&self.root)
+            .await
+            .map_err(read_error)?;
+        let file: PathBuf = tokio::fs::canonicalize(self.root.join(path))
+            .await
+            .map_err(read_error)?;
+        if !file.starts_with(&root) {
+            return Err(format!("{} is outside the project", path.display()));
+        }
+        let contents: String = tokio::fs::read_to_string(file).await.map_err(read_error)?;
+        return Ok(Value::String(contents));
+    }
+}
+
+// Builds the project and reports the compiler errors
+pub struct CargoBuildTool {
+    project_path: PathBuf,
+}
+
+impl CargoBuildTool {
+    pub fn new(project_path: &str) -> Self {
+        return Self {
+            project_path: PathBuf::from(project_path),
+        };
+    }
+}
+
+#[async_trait]
+impl AgentTool for CargoBuildTool {
+    fn declaration(&self) -> FunctionDeclaration {
+        return FunctionDeclaration {
+            name: "cargo_build".to_string(),
+            description: "Build the web server project with cargo and list the compiler errors"
+                .to_string(),
+            parameters: None,
+        };
+    }
+
+    async fn call(&self, args: &Value) -> Result<Value, String> {
+        let output: std::process::Output = tokio::process::Command::new("cargo")
+            .arg("build")
+            .arg("--message-format=json")
+            .current_dir(&self.project_path)
+            .output()
+            .await
+            .map_err(|error| format!("Failed to run cargo: {}", error))?;
+
+        let errors: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+            .filter(|message| {
+                message["reason"] == "compiler-message" && message["message"]["level"] == "error"
+            })
+            .filter_map(|message| message["message"]["rendered"].as_str().map(String::from))
+            .collect();
+        return Ok(json!({ "success": output.status.success(), "errors": errors }));
+    }
+}
+
+// Checks which status code an url answers with
+pub struct ProbeUrlTool {
+    client: Client,
+}
+
+impl ProbeUrlTool {
+    pub fn new() -> Self {
+        let client: Client = Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .expect("Failed to build the url probe client");
+        return Self { client };
+    }
+}
+
+#[async_trait]
+impl AgentTool for ProbeUrlTool {
+    fn declaration(&self) -> FunctionDeclaration {
+        return FunctionDeclaration {
+            name: "probe_url".to_string(),
+            description: "Send a GET request to an url and return the status code".to_string(),
+            parameters: Some(json!({
+                "type": "OBJECT",
+                "properties": { "url": { "type": "STRING" } },
+                "required": ["url"]
+            })),
+        };
+    }
+
+    async fn call(&self, args: &Value) -> Result<Value, String> {
+        let url: &str = string_arg(args, "url")?;
+        let status: u16 = check_status_code(&self.client, url)
+            .await
+            .map_err(|error| format!("Failed to reach {}: {}", url, error))?;
+        return Ok(json!({ "status": status }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tool_box_dispatch() {
+        let root: PathBuf = std::env::temp_dir().join("tool_box_dispatch");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        let tools: ToolBox = ToolBox::new()
+            .with(ReadFileTool::new(root.to_str().unwrap()))
+            .with(ProbeUrlTool::new());
+        let call = |name: &str, args: Value| FunctionCall {
+            name: name.to_string(),
+            args,
+        };
+
+        let declarations: Value = serde_json::to_value(tools.declarations()).unwrap();
+        assert_eq!(
+            declarations[0]["function_declarations"][1]["name"],
+            "probe_url"
+        );
+
+        let response: FunctionResponse = tools
+            .dispatch(&call("read_file", json!({ "path": "src/main.rs" })))
+            .await;
+        assert_eq!(response.response, json!({ "output": "fn main() {}" }));
+
+        // Failures are answered, not raised, so the model can correct itself
+        let response: FunctionResponse = tools
+            .dispatch(&call("read_file", json!({ "path": "../../etc/passwd" })))
+            .await;
+        assert_eq!(
+            response.response,
+            json!({ "error": "../../etc/passwd is outside the project" })
+        );
+        #[cfg(unix)]
+        {
+            let secret: PathBuf = std::env::temp_dir().join("tool_box_dispatch_secret");
+            std::fs::write(&secret, "password").unwrap();
+            let link: PathBuf = root.join("src/secret.txt");
+            let _ = std::fs::remove_file(&link);
+            std::os::unix::fs::symlink(&secret, &link).unwrap();
+            let response: FunctionResponse = tools
+                .dispatch(&call("read_file", json!({ "path": "src/secret.txt" })))
+                .await;
+            assert_eq!(
+                response.response,
+                json!({ "error": "src/secret.txt is outside the project" })
+            );
+        }
+        let response: FunctionResponse = tools.dispatch(&call("delete_file", json!({}))).await;
+        assert_eq!(
+            response.response,
+            json!({ "error": "There is no tool named delete_file" })
+        );
+    }
+}
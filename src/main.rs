@@ -1,34 +1,64 @@
-#[macro_export]
-macro_rules! get_function_string {
-    ($func: ident) => {{ stringify!($func) }};
-}
-
-#[macro_use]
 mod ai_functions;
 mod apis;
 mod helpers;
 mod models;
+#[cfg(test)]
+mod test_support;
 
-use apis::call_request::call_gemini;
+use ai_functions::prompts::PromptRegistry;
+use apis::llm_provider::{LlmProvider, build_provider};
+use helpers::attachments::load_attachments;
 use helpers::command_line::get_user_reponse;
-use models::general::llm::{Message, MessagePart, MessagePartText};
+use helpers::config::AppConfig;
+use models::agents_manager::managing_agent::ManagingAgent;
+use models::general::llm::InlineData;
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() {
+    // Files to send along with the request, e.g. `cargo run -- wireframe.png spec.pdf`
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    let attachments: Vec<InlineData> = match load_attachments(&paths) {
+        Ok(attachments) => attachments,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
     let user_input: String = get_user_reponse("What webserver are we building today?");
 
-    let gemini_prompt: Message = Message {
-        contents: vec![MessagePart {
-            parts: vec![MessagePartText {
-                text: user_input.to_string(),
-            }],
-        }],
-        generation_config: None,
+    // A broken prompt override is reported before anything is sent to the model
+    let config: AppConfig = AppConfig::load();
+    let prompts: Arc<PromptRegistry> = match PromptRegistry::load(&config.prompts) {
+        Ok(prompts) => Arc::new(prompts),
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
     };
 
-    let gemini_response: String = match call_gemini(&gemini_prompt).await {
-        Ok(response) => response,
-        Err(_) => call_gemini(&gemini_prompt).await.unwrap(),
+    let llm: Arc<dyn LlmProvider> = match build_provider(&config) {
+        Ok(llm) => llm,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let mut manager: ManagingAgent = match ManagingAgent::new(
+        user_input,
+        attachments,
+        llm,
+        prompts,
+        config.memory.clone(),
+    )
+    .await
+    {
+        Ok(manager) => manager,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
     };
-    println!("{}", gemini_response);
+    manager.execute_project().await;
 }
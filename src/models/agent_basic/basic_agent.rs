@@ -1,12 +1,12 @@
 use super::basic_traits::BasicTraits;
-use crate::models::general::llm::Message;
+use super::memory::Memory;
 
 #[derive(Debug)]
 pub struct BasicAgent {
     pub objective: String,
     pub position: String,
     pub state: AgentState,
-    pub memory: Vec<Message>,
+    pub memory: Memory,
 }
 
 #[allow(unused)]
@@ -21,10 +21,10 @@ pub enum AgentState {
 impl BasicTraits for BasicAgent {
     fn new(objective: String, position: String) -> Self {
         return Self {
-            objective: objective,
-            position: position,
+            objective,
+            position,
             state: AgentState::Discovery,
-            memory: Vec::from([]),
+            memory: Memory::default(),
         };
     }
 
@@ -44,7 +44,7 @@ impl BasicTraits for BasicAgent {
         return &self.state;
     }
 
-    fn get_memory(&self) -> &Vec<Message> {
+    fn get_memory(&self) -> &Memory {
         return &self.memory;
     }
 }
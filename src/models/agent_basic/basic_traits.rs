@@ -1,6 +1,6 @@
 #![allow(unused)]
 use super::basic_agent::AgentState;
-use crate::models::general::llm::Message;
+use super::memory::Memory;
 
 pub trait BasicTraits {
     fn new(objective: String, position: String) -> Self;
@@ -8,5 +8,5 @@ pub trait BasicTraits {
     fn get_objective(&self) -> &String;
     fn get_position(&self) -> &String;
     fn get_state(&self) -> &AgentState;
-    fn get_memory(&self) -> &Vec<Message>;
+    fn get_memory(&self) -> &Memory;
 }
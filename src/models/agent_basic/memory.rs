@@ -0,0 +1,554 @@
+#![allow(unused)]
+use crate::apis::llm_error::LlmError;
+use crate::apis::llm_provider::{CachedContent, CallContext, LlmProvider, estimate_tokens};
+use crate::helpers::command_line::PrintCommand;
+use crate::models::general::llm::{Message, MessagePart, ROLE_USER};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+const SUMMARIZE_INSTRUCTION: &str = "Summarize the conversation above for yourself. Keep every \
+    decision, requirement and error that still matters, leave out code which will be rewritten.";
+const SUMMARY_PREFIX: &str = "Summary of the conversation so far: ";
+const SHARED_CONTEXT_PREFIX: &str = "Context for everything that follows: ";
+const ACKNOWLEDGEMENT: &str = "Understood.";
+// An uploaded context is replaced this long before it expires, so no request refers to it after
+const CACHED_CONTENT_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+// What to do with old turns once a request no longer fits in the context window
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextStrategy {
+    // Let the model summarize the older turns, drop turns when that is not enough
+    #[default]
+    Summarize,
+    // Drop the oldest turns
+    Drop,
+}
+
+// How much of the conversation an agent keeps
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct MemoryConfig {
+    // Older turns are dropped, a question and its answer count as two turns
+    #[serde(default = "default_max_turns")]
+    pub max_turns: usize,
+    // Largest request in tokens, gemini-2.0-flash accepts 1,048,576 input tokens
+    #[serde(default = "default_max_context_tokens")]
+    pub max_context_tokens: u32,
+    #[serde(default)]
+    pub strategy: ContextStrategy,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        return Self {
+            max_turns: default_max_turns(),
+            max_context_tokens: default_max_context_tokens(),
+            strategy: ContextStrategy::default(),
+        };
+    }
+}
+
+fn default_max_turns() -> usize {
+    return 12;
+}
+
+fn default_max_context_tokens() -> u32 {
+    return 1_000_000;
+}
+
+// Conversation of an agent with the model, as alternating user and model turns, optionally
+// preceded by a large context which stays the same for the whole run
+#[derive(Debug, Clone)]
+pub struct Memory {
+    turns: Vec<MessagePart>,
+    shared: Option<String>,
+    // AI function -> uploaded shared context, None when it could not be uploaded
+    cached_contents: BTreeMap<String, Option<CachedContent>>,
+    max_turns: usize,
+    max_context_tokens: u32,
+    strategy: ContextStrategy,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        return Self::new(&MemoryConfig::default());
+    }
+}
+
+impl Memory {
+    pub fn new(config: &MemoryConfig) -> Self {
+        return Self {
+            turns: vec![],
+            shared: None,
+            cached_contents: BTreeMap::new(),
+            max_turns: config.max_turns,
+            max_context_tokens: config.max_context_tokens,
+            strategy: config.strategy,
+        };
+    }
+
+    pub fn turns(&self) -> &Vec<MessagePart> {
+        return &self.turns;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.turns.is_empty();
+    }
+
+    // Remember a question and the answer of the model, dropping the oldest exchanges
+    pub fn remember(&mut self, question: MessagePart, answer: MessagePart) {
+        self.turns.push(question);
+        self.turns.push(answer);
+        self.trim();
+    }
+
+    pub fn clear(&mut self) {
+        self.turns.clear();
+    }
+
+    // Put a context (e.g. the code template and project description) before every request. It is
+    // uploaded once per AI function where the backend can cache it, and sent along otherwise.
+    pub fn share(&mut self, context: String) {
+        if self.shared.as_ref() != Some(&context) {
+            self.shared = Some(context);
+            self.cached_contents.clear();
+        }
+    }
+
+    // The message preceded by the shared context and the remembered turns
+    pub fn conversation_with(&self, message: &Message, context: &CallContext) -> Message {
+        let mut conversation: Message = message.clone();
+        let cached_content: Option<&CachedContent> = self
+            .cached_contents
+            .get(&context.function)
+            .and_then(|cached| cached.as_ref())
+            .filter(|cached| !expires_within(cached, Duration::ZERO));
+        let shared_turns: Vec<MessagePart> = match (&self.shared, cached_content) {
+            (Some(_), Some(cached)) => {
+                // The system instruction is part of the uploaded context
+                conversation.cached_content = Some(cached.name.clone());
+                conversation.system_instruction = None;
+                vec![]
+            }
+            (Some(shared), None) => shared_turns(shared),
+            (None, _) => vec![],
+        };
+        conversation.contents = shared_turns
+            .into_iter()
+            .chain(self.turns.iter().cloned())
+            .chain(message.contents.iter().cloned())
+            .collect();
+        return conversation;
+    }
+
+    // Upload the shared context with the system instruction of the message, once per AI function
+    // since every function may have its own model, and again when the upload is about to expire
+    async fn cache_shared(
+        &mut self,
+        llm: &dyn LlmProvider,
+        message: &Message,
+        context: &CallContext,
+    ) {
+        let Some(shared) = &self.shared else {
+            return;
+        };
+        match self.cached_contents.get(&context.function) {
+            // The backend refused it before, keep sending it along
+            Some(None) => return,
+            Some(Some(cached)) if !expires_within(cached, CACHED_CONTENT_EXPIRY_MARGIN) => return,
+            _ => (),
+        }
+        let prefix: Message = Message {
+            contents: shared_turns(shared),
+            system_instruction: message.system_instruction.clone(),
+            ..Default::default()
+        };
+        let cached: Option<CachedContent> = match llm.create_cached_content(&prefix, context).await
+        {
+            Ok(cached) => cached,
+            Err(e) => {
+                PrintCommand::Issue.print_agent_message(
+                    &context.agent,
+                    &format!(
+                        "Failed to cache the shared context, sending it along: {}",
+                        e
+                    ),
+                );
+                None
+            }
+        };
+        self.cached_contents
+            .insert(context.function.clone(), cached);
+    }
+
+    // The message preceded by as much of the conversation as fits in the context window.
+    // Older turns are summarized or dropped until the request fits.
+    pub async fn fit_in_context(
+        &mut self,
+        llm: &dyn LlmProvider,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<Message, LlmError> {
+        self.cache_shared(llm, message, context).await;
+        let mut summarized: bool = false;
+        loop {
+            let request: Message = self.conversation_with(message, context);
+            if self.turns.is_empty() {
+                return Ok(request);
+            }
+            let tokens: u32 = llm.count_tokens(&request, context).await?;
+            if tokens <= self.max_context_tokens {
+                return Ok(request);
+            }
+
+            PrintCommand::Issue.print_agent_message(
+                &context.agent,
+                &format!(
+                    "The conversation ({} tokens) does not fit in {} tokens, compacting the memory.",
+                    tokens, self.max_context_tokens
+                ),
+            );
+            // The older turns have to fit in a request themselves to be summarized
+            let older_tokens: u32 = estimate_tokens(&Message {
+                contents: self.older_turns().to_vec(),
+                ..Default::default()
+            });
+            if self.strategy == ContextStrategy::Summarize
+                && !summarized
+                && !self.older_turns().is_empty()
+                && older_tokens <= self.max_context_tokens
+            {
+                self.summarize(llm, context).await?;
+                summarized = true;
+            } else {
+                self.drop_oldest();
+            }
+        }
+    }
+
+    // Every turn except the latest exchange, which is kept word for word
+    fn older_turns(&self) -> &[MessagePart] {
+        return &self.turns[..self.turns.len().saturating_sub(2)];
+    }
+
+    // Replace the older turns by a summary written by the model
+    async fn summarize(
+        &mut self,
+        llm: &dyn LlmProvider,
+        context: &CallContext,
+    ) -> Result<(), LlmError> {
+        let mut contents: Vec<MessagePart> = self.older_turns().to_vec();
+        contents.push(MessagePart::user(SUMMARIZE_INSTRUCTION.to_string()));
+        let request: Message = Message {
+            contents,
+            ..Default::default()
+        };
+        PrintCommand::AICall.print_agent_message(&context.agent, "Summarizing the memory");
+        let summary: String = llm
+            .generate(
+                &request,
+                &CallContext::new(&context.agent, "summarize_memory"),
+            )
+            .await?
+            .text;
+
+        let latest: Vec<MessagePart> = self.turns.split_off(self.older_turns().len());
+        self.turns = vec![
+            MessagePart::user(format!("{}{}", SUMMARY_PREFIX, summary)),
+            MessagePart::model(ACKNOWLEDGEMENT.to_string()),
+        ];
+        self.turns.extend(latest);
+        return Ok(());
+    }
+
+    // Drop the oldest exchange
+    fn drop_oldest(&mut self) {
+        self.turns.remove(0);
+        self.drop_leading_answers();
+    }
+
+    // Drop whole exchanges so the conversation still starts with a user turn
+    fn trim(&mut self) {
+        while self.turns.len() > self.max_turns && !self.turns.is_empty() {
+            self.drop_oldest();
+        }
+    }
+
+    fn drop_leading_answers(&mut self) {
+        while self
+            .turns
+            .first()
+            .is_some_and(|turn| turn.role.as_deref() != Some(ROLE_USER))
+        {
+            self.turns.remove(0);
+        }
+    }
+}
+
+// Whether an uploaded context is gone, or will be, within the margin
+fn expires_within(cached: &CachedContent, margin: Duration) -> bool {
+    return Instant::now() + margin >= cached.expires_at;
+}
+
+// The shared context as an exchange, so the conversation after it still starts with a user turn
+fn shared_turns(shared: &str) -> Vec<MessagePart> {
+    return vec![
+        MessagePart::user(format!("{}{}", SHARED_CONTEXT_PREFIX, shared)),
+        MessagePart::model(ACKNOWLEDGEMENT.to_string()),
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scripted_provider::ScriptedProvider;
+
+    #[test]
+    fn test_memory_trims_whole_exchanges() {
+        let mut memory: Memory = Memory::new(&MemoryConfig {
+            max_turns: 4,
+            ..Default::default()
+        });
+        for round in 1..=3 {
+            memory.remember(
+                MessagePart::user(format!("question {}", round)),
+                MessagePart::model(format!("answer {}", round)),
+            );
+        }
+
+        let texts: Vec<String> = memory
+            .turns()
+            .iter()
+            .map(|turn| turn.joined_text())
+            .collect();
+        assert_eq!(
+            texts,
+            vec!["question 2", "answer 2", "question 3", "answer 3"]
+        );
+        assert_eq!(memory.turns()[0].role.as_deref(), Some(ROLE_USER));
+
+        // An odd limit still never starts with an answer
+        let mut memory: Memory = Memory::new(&MemoryConfig {
+            max_turns: 3,
+            ..Default::default()
+        });
+        memory.remember(
+            MessagePart::user("question 1".to_string()),
+            MessagePart::model("answer 1".to_string()),
+        );
+        memory.remember(
+            MessagePart::user("question 2".to_string()),
+            MessagePart::model("answer 2".to_string()),
+        );
+        assert_eq!(memory.turns().len(), 2);
+        assert_eq!(memory.turns()[0].joined_text(), "question 2");
+    }
+
+    // 100 tokens of old conversation, 20 of the latest exchange and 10 for the new question
+    fn full_memory(strategy: ContextStrategy) -> (Memory, Message) {
+        let mut memory: Memory = Memory::new(&MemoryConfig {
+            max_context_tokens: 100,
+            strategy,
+            ..Default::default()
+        });
+        memory.remember(
+            MessagePart::user("q".repeat(200)),
+            MessagePart::model("a".repeat(200)),
+        );
+        memory.remember(
+            MessagePart::user("latest question".repeat(2)),
+            MessagePart::model("latest code".repeat(3)),
+        );
+        let message: Message = Message {
+            contents: vec![MessagePart::user("x".repeat(40))],
+            ..Default::default()
+        };
+        return (memory, message);
+    }
+
+    #[tokio::test]
+    async fn test_memory_summarizes_to_fit_in_context() {
+        let llm: ScriptedProvider = ScriptedProvider::new(vec![Ok("Wants a todo app".to_string())]);
+        let (mut memory, message) = full_memory(ContextStrategy::Summarize);
+        let context: CallContext = CallContext::new("Backend Developer", "print_fixed_code");
+
+        let request: Message = memory
+            .fit_in_context(&llm, &message, &context)
+            .await
+            .unwrap();
+
+        // The old exchange became a summary, the latest exchange is kept as it was
+        assert!(estimate_tokens(&request) <= 100);
+        assert_eq!(request.contents.len(), 5);
+        assert_eq!(
+            request.contents[0].joined_text(),
+            "Summary of the conversation so far: Wants a todo app"
+        );
+        assert_eq!(request.contents[3].joined_text(), "latest code".repeat(3));
+        assert_eq!(llm.contexts()[0].function, "summarize_memory");
+        assert!(
+            llm.received()[0].contents[0]
+                .joined_text()
+                .starts_with("qqq")
+        );
+    }
+
+    fn instructed(text: &str) -> Message {
+        return Message {
+            contents: vec![MessagePart::user(text.to_string())],
+            system_instruction: Some(MessagePart::text("You are a function printer".to_string())),
+            ..Default::default()
+        };
+    }
+
+    #[tokio::test]
+    async fn test_memory_uploads_shared_context_once() {
+        use crate::apis::call_request::GeminiClient;
+        use crate::test_support::mock_gemini::{MockGemini, MockReply};
+
+        let mock: MockGemini = MockGemini::start().await;
+        mock.respond(
+            "print_fixed_code",
+            MockReply::Text("fn main() {}".to_string()),
+        );
+        let llm: GeminiClient = GeminiClient::new(&mock.config()).unwrap();
+        let context: CallContext = CallContext::new("Backend Developer", "print_fixed_code");
+        let mut memory: Memory = Memory::default();
+        memory.share(format!(
+            "CODE TEMPLATE: {}",
+            "use actix_web;\n".repeat(1200)
+        ));
+
+        for round in 1..=2 {
+            let message: Message = instructed(&format!("print_fixed_code round {}", round));
+            let request: Message = memory
+                .fit_in_context(&llm, &message, &context)
+                .await
+                .unwrap();
+            let answer: String = llm.generate(&request, &context).await.unwrap().text;
+            memory.remember(message.contents[0].clone(), MessagePart::model(answer));
+        }
+
+        let requests = mock.requests();
+        let uploads: Vec<serde_json::Value> = requests
+            .iter()
+            .filter(|request| request.path == "/cachedContents")
+            .map(|request| request.json())
+            .collect();
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0]["model"], "models/gemini-2.0-flash");
+        assert_eq!(uploads[0]["ttl"], "3600s");
+        assert_eq!(
+            uploads[0]["system_instruction"]["parts"][0]["text"],
+            "You are a function printer"
+        );
+
+        // The requests only carry the conversation, the mock refuses a system instruction next
+        // to a cached content
+        let last: serde_json::Value = requests.last().unwrap().json();
+        assert_eq!(last["cached_content"], "cachedContents/mock-0");
+        assert_eq!(last["contents"].as_array().unwrap().len(), 3);
+        assert_eq!(mock.calls_for("print_fixed_code"), 2);
+    }
+
+    #[tokio::test]
+    async fn test_memory_replaces_expiring_shared_context() {
+        use crate::apis::call_request::GeminiClient;
+        use crate::helpers::config::GeminiConfig;
+        use crate::test_support::mock_gemini::{MockGemini, MockReply};
+
+        let mock: MockGemini = MockGemini::start().await;
+        mock.respond(
+            "print_fixed_code",
+            MockReply::Text("fn main() {}".to_string()),
+        );
+        // Every upload expires within the margin, so each request needs a new one
+        let config: GeminiConfig = GeminiConfig {
+            cached_content_ttl_secs: 30,
+            ..mock.config()
+        };
+        let llm: GeminiClient = GeminiClient::new(&config).unwrap();
+        let context: CallContext = CallContext::new("Backend Developer", "print_fixed_code");
+        let mut memory: Memory = Memory::default();
+        memory.share(format!(
+            "CODE TEMPLATE: {}",
+            "use actix_web;\n".repeat(1200)
+        ));
+
+        let mut requests: Vec<Message> = vec![];
+        for round in 1..=2 {
+            let message: Message = instructed(&format!("print_fixed_code round {}", round));
+            requests.push(
+                memory
+                    .fit_in_context(&llm, &message, &context)
+                    .await
+                    .unwrap(),
+            );
+        }
+        assert_eq!(
+            requests[0].cached_content.as_deref(),
+            Some("cachedContents/mock-0")
+        );
+        assert_eq!(
+            requests[1].cached_content.as_deref(),
+            Some("cachedContents/mock-1")
+        );
+
+        // Below the minimum of the model the context is sent along without trying an upload
+        let mut memory: Memory = Memory::default();
+        memory.share(format!("CODE TEMPLATE: {}", "use actix_web;\n".repeat(400)));
+        let request: Message = memory
+            .fit_in_context(&llm, &instructed("print_fixed_code"), &context)
+            .await
+            .unwrap();
+        assert_eq!(request.cached_content, None);
+        assert!(request.system_instruction.is_some());
+        let uploads: usize = mock
+            .requests()
+            .iter()
+            .filter(|request| request.path == "/cachedContents")
+            .count();
+        assert_eq!(uploads, 2);
+    }
+
+    #[tokio::test]
+    async fn test_memory_sends_shared_context_along() {
+        let llm: ScriptedProvider = ScriptedProvider::new(vec![]);
+        let context: CallContext = CallContext::new("Backend Developer", "print_fixed_code");
+        let mut memory: Memory = Memory::default();
+        memory.share("PROJECT DESCRIPTION: a todo app".to_string());
+
+        let request: Message = memory
+            .fit_in_context(&llm, &instructed("print_fixed_code"), &context)
+            .await
+            .unwrap();
+        assert_eq!(request.cached_content, None);
+        assert!(request.system_instruction.is_some());
+        assert_eq!(
+            request.contents[0].joined_text(),
+            "Context for everything that follows: PROJECT DESCRIPTION: a todo app"
+        );
+        assert_eq!(request.contents[1].joined_text(), "Understood.");
+        assert_eq!(request.contents[2].joined_text(), "print_fixed_code");
+    }
+
+    #[tokio::test]
+    async fn test_memory_drops_to_fit_in_context() {
+        let llm: ScriptedProvider = ScriptedProvider::new(vec![]);
+        let (mut memory, message) = full_memory(ContextStrategy::Drop);
+        let context: CallContext = CallContext::new("Backend Developer", "print_fixed_code");
+
+        let request: Message = memory
+            .fit_in_context(&llm, &message, &context)
+            .await
+            .unwrap();
+        assert_eq!(request.contents.len(), 3);
+        assert_eq!(
+            request.contents[0].joined_text(),
+            "latest question".repeat(2)
+        );
+        assert_eq!(memory.turns().len(), 2);
+        assert_eq!(llm.calls(), 0);
+    }
+}
@@ -1,63 +1,83 @@
-use crate::ai_functions::ai_func_architect::{print_project_scope, print_site_urls};
+use crate::ai_functions::prompts::{PRINT_PROJECT_SCOPE, PRINT_SITE_URLS, Prompt, PromptRegistry};
+use crate::apis::llm_error::LlmError;
+use crate::apis::llm_provider::LlmProvider;
 use crate::helpers::command_line::PrintCommand;
 use crate::helpers::general::{ai_task_request_decoded, check_status_code};
 use crate::models::agent_basic::basic_agent::{AgentState, BasicAgent};
 use crate::models::agent_basic::basic_traits::BasicTraits;
+use crate::models::agent_basic::memory::Memory;
 use crate::models::agents::agent_traits::{FactSheet, ProjectScope, SpecialFunctions};
 
 // use crossterm::cursor::position;
 use reqwest::Client;
+use std::sync::Arc;
 use std::time::Duration;
 
 // Solutions architect
 #[derive(Debug)]
 pub struct AgentSolutionArchitect {
     attributes: BasicAgent,
+    llm: Arc<dyn LlmProvider>,
+    prompts: Arc<PromptRegistry>,
 }
 
 impl AgentSolutionArchitect {
-    pub fn new() -> Self {
+    pub fn new(llm: Arc<dyn LlmProvider>, prompts: Arc<PromptRegistry>) -> Self {
         let attributes: BasicAgent = BasicAgent {
             objective: "Gathers information and design solutions for website development"
                 .to_string(),
             position: "Solutions architect".to_string(),
             state: AgentState::Discovery,
-            memory: vec![],
+            memory: Memory::default(),
+        };
+        return Self {
+            attributes,
+            llm,
+            prompts,
         };
-        return Self { attributes };
     }
 
-    async fn retrieve_project_scope(&mut self, factsheet: &mut FactSheet) -> ProjectScope {
-        let msg_context: String = format!("{}", factsheet.project_description);
+    async fn retrieve_project_scope(
+        &mut self,
+        factsheet: &mut FactSheet,
+    ) -> Result<ProjectScope, LlmError> {
+        let prompt: Prompt = self.prompts.render(
+            PRINT_PROJECT_SCOPE,
+            &[("project_description", &factsheet.project_description)],
+        );
         let ai_response: ProjectScope = ai_task_request_decoded::<ProjectScope>(
-            msg_context,
+            self.llm.as_ref(),
+            &prompt,
+            &factsheet.attachments,
             &self.attributes.position,
-            get_function_string!(print_project_scope),
-            print_project_scope,
         )
-        .await;
+        .await?;
 
         factsheet.project_scope = Some(ai_response);
         self.attributes.update_state(AgentState::Finished);
 
-        return ai_response;
+        return Ok(ai_response);
     }
 
     async fn retrieve_determine_external_urls(
         &mut self,
         factsheet: &mut FactSheet,
         msg_context: String,
-    ) {
+    ) -> Result<(), LlmError> {
+        let prompt: Prompt = self
+            .prompts
+            .render(PRINT_SITE_URLS, &[("project_description", &msg_context)]);
         let ai_response: Vec<String> = ai_task_request_decoded::<Vec<String>>(
-            msg_context,
+            self.llm.as_ref(),
+            &prompt,
+            &factsheet.attachments,
             &self.attributes.position,
-            get_function_string!(print_site_urls),
-            print_site_urls,
         )
-        .await;
+        .await?;
 
         factsheet.external_urls = Some(ai_response);
         self.attributes.state = AgentState::UnitTesting;
+        return Ok(());
     }
 }
 
@@ -75,13 +95,13 @@ impl SpecialFunctions for AgentSolutionArchitect {
         while self.attributes.state != AgentState::Finished {
             match self.attributes.state {
                 AgentState::Discovery => {
-                    let project_scope = self.retrieve_project_scope(factsheet).await;
+                    let project_scope = self.retrieve_project_scope(factsheet).await?;
                     if project_scope.is_external_urls_required {
                         self.retrieve_determine_external_urls(
                             factsheet,
                             factsheet.project_description.clone(),
                         )
-                        .await;
+                        .await?;
                         self.attributes.state = AgentState::UnitTesting;
                     }
                 }
@@ -123,7 +143,7 @@ impl SpecialFunctions for AgentSolutionArchitect {
                             .as_ref()
                             .unwrap()
                             .iter()
-                            .filter(|url| !exclude_urls.contains(&url))
+                            .filter(|url| !exclude_urls.contains(url))
                             .cloned()
                             .collect();
                         factsheet.external_urls = Some(new_urls);
@@ -145,10 +165,14 @@ impl SpecialFunctions for AgentSolutionArchitect {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::cassettes::cassette_provider;
 
     #[tokio::test]
     async fn tests_solutions_architect() {
-        let mut agent: AgentSolutionArchitect = AgentSolutionArchitect::new();
+        let mut agent: AgentSolutionArchitect = AgentSolutionArchitect::new(
+            cassette_provider("solutions_architect"),
+            Arc::new(PromptRegistry::defaults()),
+        );
 
         let mut fact_sheet = FactSheet {
             project_description: "Build a full stack website with user login and logout that shows latest Forex prices".to_string(),
@@ -156,7 +180,7 @@ mod tests {
             external_urls: None,
             backend_code: None,
             api_endpoint_schema: None,
-
+            attachments: vec![],
         };
 
         // let project_scope = agent.retrieve_project_scope(&mut fact_sheet).await;
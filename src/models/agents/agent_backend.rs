@@ -1,14 +1,18 @@
 #![allow(unused)]
-use crate::ai_functions::ai_func_backend::{
-    print_backend_webserver_code, print_fixed_code, print_improved_webserver_code,
-    print_rest_api_endpoints,
+use crate::ai_functions::prompts::{
+    PRINT_BACKEND_WEBSERVER_CODE, PRINT_FIXED_CODE, PRINT_IMPROVED_WEBSERVER_CODE,
+    PRINT_REST_API_ENDPOINTS, Prompt, PromptRegistry,
 };
+use crate::apis::llm_error::LlmError;
+use crate::apis::llm_provider::LlmProvider;
 use crate::helpers::command_line::{ confirm_safe_code, PrintCommand };
 use crate::helpers::general::{
-    ai_task_request, check_status_code, read_code_template_contents,
-    read_code_template_output_contents, save_api_endpoint, save_backend_code, WEB_SERVER_PROJECT_PATH,
+    ai_task_request, ai_task_request_decoded, ai_task_request_streamed, check_status_code,
+    read_code_template_contents, read_code_template_output_contents, save_api_endpoint,
+    save_backend_code, WEB_SERVER_PROJECT_PATH,
 };
 use crate::models::agent_basic::basic_agent::{AgentState, BasicAgent};
+use crate::models::agent_basic::memory::{Memory, MemoryConfig};
 use crate::models::agents::agent_traits::{FactSheet, ProjectScope, SpecialFunctions};
 
 use async_trait::async_trait;
@@ -17,6 +21,7 @@ use serde_json::json;
 use std::f32::consts::E;
 use std::fs;
 use std::process::{Command, Stdio};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::time;
 
@@ -26,115 +31,168 @@ use super::agent_traits::RouteObject;
 #[derive(Debug)]
 pub struct AgentBackendDeveloper {
     attributes: BasicAgent,
+    llm: Arc<dyn LlmProvider>,
+    prompts: Arc<PromptRegistry>,
     bug_errors: Option<String>,
     bug_fix_tries: u8,
 }
 
 impl AgentBackendDeveloper {
-    pub fn new() -> Self {
+    pub fn new(
+        llm: Arc<dyn LlmProvider>,
+        prompts: Arc<PromptRegistry>,
+        memory_config: &MemoryConfig,
+    ) -> Self {
         let attributes: BasicAgent = BasicAgent {
             objective: "Develops backend code for webserver and json database".to_string(),
             position: "Backend developer".to_string(),
             state: AgentState::Discovery,
-            memory: vec![],
+            memory: Memory::new(memory_config),
         };
         return Self {
             attributes,
+            llm,
+            prompts,
             bug_errors: None,
             bug_fix_tries: 0,
         };
     }
 
-    async fn call_initial_backend_code(&mut self, fact_sheet: &mut FactSheet) {
+    async fn call_initial_backend_code(
+        &mut self,
+        fact_sheet: &mut FactSheet,
+    ) -> Result<(), LlmError> {
         //Read in the code template
         let code_template = read_code_template_contents();
 
-        //Concatenate instruction
-        let mut msg_context: String = format!(
-            "CODE TEMPLATE: {}\n PROJECT DESCRIPTION: {}\n",
-            code_template, fact_sheet.project_description
+        //Fill in the prompt
+        let prompt: Prompt = self.prompts.render(
+            PRINT_BACKEND_WEBSERVER_CODE,
+            &[
+                ("code_template", &code_template),
+                ("project_description", &fact_sheet.project_description),
+            ],
         );
 
         // Generate initial code
-        let ai_response: String = ai_task_request(
-            msg_context,
+        let ai_response: String = ai_task_request_streamed(
+            self.llm.as_ref(),
+            &mut self.attributes.memory,
+            &prompt,
             &self.attributes.position,
-            get_function_string!(print_backend_webserver_code),
-            print_backend_webserver_code,
         )
-        .await;
+        .await?;
         assert!(!ai_response.contains("```"), "Detected codeblocks in the result from call_initial_backend_code!");
         save_backend_code(&ai_response);
         fact_sheet.backend_code = Some(ai_response);
+        return Ok(());
     }
 
-    async fn call_improved_backend_code(&mut self, fact_sheet: &mut FactSheet) {
-        //Concatenate instruction
-        let mut msg_context: String = format!(
-            "CODE TEMPLATE: {:?}\n PROJECT DESCRIPTION: {:?}\n",
-            fact_sheet.backend_code, fact_sheet
+    // The template, project description and API schema stay the same while the code is improved
+    // and fixed, so they are uploaded once instead of being resent with every call
+    fn share_project_context(&mut self, fact_sheet: &FactSheet) {
+        let shared_context: String = format!(
+            "ORIGINAL CODE TEMPLATE: {}\n PROJECT DESCRIPTION: {}\n PROJECT SCOPE: {:?}\n EXTERNAL URLS: {:?}\n API SCHEMA: {:?}\n",
+            read_code_template_contents(),
+            fact_sheet.project_description,
+            fact_sheet.project_scope,
+            fact_sheet.external_urls,
+            fact_sheet.api_endpoint_schema
+        );
+        self.attributes.memory.share(shared_context);
+    }
+
+    async fn call_improved_backend_code(
+        &mut self,
+        fact_sheet: &mut FactSheet,
+    ) -> Result<(), LlmError> {
+        //Fill in the prompt, the project description is in the shared context
+        self.share_project_context(fact_sheet);
+        let backend_code: String = format!("{:?}", fact_sheet.backend_code);
+        let prompt: Prompt = self.prompts.render(
+            PRINT_IMPROVED_WEBSERVER_CODE,
+            &[
+                ("backend_code", &backend_code),
+                ("project_description", "as given in the context above"),
+            ],
         );
 
         // Generate initial code
-        let ai_response: String = ai_task_request(
-            msg_context,
+        let ai_response: String = ai_task_request_streamed(
+            self.llm.as_ref(),
+            &mut self.attributes.memory,
+            &prompt,
             &self.attributes.position,
-            get_function_string!(print_improved_webserver_code),
-            print_improved_webserver_code,
         )
-        .await;
+        .await?;
         assert!(!ai_response.contains("```"), "Detected codeblocks in the result from call_improved_backend_code!");
         save_backend_code(&ai_response);
         fact_sheet.backend_code = Some(ai_response);
+        return Ok(());
     }
 
-    async fn call_fix_code_bugs(&mut self, fact_sheet: &mut FactSheet) {
-        //Concatenate instruction
-        let mut msg_context: String = format!(
-            "BROKEN_CODE: {:?}\n ERROR_BUGS: {:?}\n
-        THIS FUNCTION ONLY CODE. JUST OUPUT THE CODE. DO NOT PUT CODE IN CODE BLOCKS!",
-            fact_sheet.backend_code, self.bug_errors
+    async fn call_fix_code_bugs(
+        &mut self,
+        fact_sheet: &mut FactSheet,
+    ) -> Result<(), LlmError> {
+        //Fill in the prompt, the model still remembers the code it wrote last
+        self.share_project_context(fact_sheet);
+        let (broken_code, error_bugs): (String, String) = if self.attributes.memory.is_empty() {
+            (
+                format!("{:?}", fact_sheet.backend_code),
+                format!("{:?}", self.bug_errors),
+            )
+        } else {
+            (
+                "the code you just wrote.".to_string(),
+                format!("this is the compiler output for it: {:?}", self.bug_errors),
+            )
+        };
+        let prompt: Prompt = self.prompts.render(
+            PRINT_FIXED_CODE,
+            &[("broken_code", &broken_code), ("error_bugs", &error_bugs)],
         );
 
         // Generate initial code
-        let ai_response: String = ai_task_request(
-            msg_context,
+        let ai_response: String = ai_task_request_streamed(
+            self.llm.as_ref(),
+            &mut self.attributes.memory,
+            &prompt,
             &self.attributes.position,
-            get_function_string!(print_fixed_code),
-            print_fixed_code,
         )
-        .await;
+        .await?;
         assert!(!ai_response.contains("```"), "Detected codeblocks in the result from call_fix_code_bugs!");
         save_backend_code(&ai_response);
         fact_sheet.backend_code = Some(ai_response);
+        return Ok(());
     }
 
-    async fn call_rest_api_endpoints(mut self, fact_sheet: &mut FactSheet) -> String {
+    async fn call_rest_api_endpoints(
+        &mut self,
+        fact_sheet: &mut FactSheet,
+    ) -> Result<(), LlmError> {
         let backend_main_code: String = read_code_template_output_contents();
 
-        //Concatenate instruction
-        let mut msg_context: String = format!("CODE_INPUT: {}\n", backend_main_code,);
+        //Fill in the prompt
+        let prompt: Prompt = self
+            .prompts
+            .render(PRINT_REST_API_ENDPOINTS, &[("code_input", &backend_main_code)]);
 
-        // Generate initial code
-        let ai_response: String = ai_task_request(
-            msg_context,
+        // Extract the endpoints as JSON in the shape of the route objects
+        let route_objects: Vec<RouteObject> = ai_task_request_decoded(
+            self.llm.as_ref(),
+            &prompt,
+            &[],
             &self.attributes.position,
-            get_function_string!(print_rest_api_endpoints),
-            print_rest_api_endpoints,
         )
-        .await;
+        .await?;
 
         // Debugging for the returned API Endpoints
-        // dbg!(ai_response);
-        return ai_response;
-
-        // let route_object: Vec<RouteObject> = match serde_json::from_str(&ai_response){
-        //     Ok(route_object) => route_object,
-        //     Err(e) => panic!("ERROR Deserializing JSON: {} with the following error: {}", ai_response, e)
-        // };
-        // save_api_endpoint(&ai_response);
-        // fact_sheet.api_endpoint_schema = Some(route_object);
-        // return String::from("");
+        // dbg!(&route_objects);
+        let api_endpoints: String = serde_json::to_string_pretty(&route_objects)?;
+        save_api_endpoint(&api_endpoints);
+        fact_sheet.api_endpoint_schema = Some(route_objects);
+        return Ok(());
     }
 }
 
@@ -170,7 +228,7 @@ impl SpecialFunctions for AgentBackendDeveloper {
                 AgentState::UnitTesting => {
                     PrintCommand::UnitTest.print_agent_message(&self.attributes.position, "Backend code unittesting: Ensuring safe code.");
                     let response = confirm_safe_code();
-                    if response == false {
+                    if !response {
                         PrintCommand::UnitTest.print_agent_message(&self.attributes.position, "As requested stopped further UnitTesting.");
                         self.attributes.state = AgentState::Finished;
                         break;
@@ -193,13 +251,12 @@ impl SpecialFunctions for AgentBackendDeveloper {
                         let stdout = String::from_utf8_lossy(&output.stdout);
                         let mut error_count = 0;
                         for line in stdout.lines() {
-                            if let Ok(json) = serde_json::from_str::<serde_json::Value>(line) {
-                                if json["reason"] == "compiler-message" {
-                                    if let Some("error") = json["message"]["level"].as_str() {
-                                        error_count += 1;
-                                        self.bug_errors = Some(format!("{}{}", self.bug_errors.as_deref().unwrap_or(""), json["message"]["rendered"].as_str().unwrap_or("").to_string()));
-                                    }
-                                }
+                            if let Ok(json) = serde_json::from_str::<serde_json::Value>(line)
+                                && json["reason"] == "compiler-message"
+                                && let Some("error") = json["message"]["level"].as_str()
+                            {
+                                error_count += 1;
+                                self.bug_errors = Some(format!("{}{}", self.bug_errors.as_deref().unwrap_or(""), json["message"]["rendered"].as_str().unwrap_or("")));
                             }
                         }
                     
@@ -237,10 +294,12 @@ impl SpecialFunctions for AgentBackendDeveloper {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::scripted_provider::ScriptedProvider;
 
     #[tokio::test]
     async fn tests_backend_developer() {
-        let mut agent: AgentBackendDeveloper = AgentBackendDeveloper::new();
+        // Only the unit testing runs, the developer does not ask the model anything
+        let mut agent: AgentBackendDeveloper = AgentBackendDeveloper::new(Arc::new(ScriptedProvider::default()), Arc::new(PromptRegistry::defaults()), &MemoryConfig::default());
 
         let factsheet_str: &str = r#"
             {
@@ -1,7 +1,9 @@
 #![allow(unused)]
 use crate::models::agent_basic::basic_agent::BasicAgent;
+use crate::models::general::llm::InlineData;
+use crate::models::general::response_schema::ResponseSchema;
 use async_trait::async_trait;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::fmt::Debug;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -11,6 +13,9 @@ pub struct FactSheet {
     pub external_urls: Option<Vec<String>>,
     pub backend_code: Option<String>,
     pub api_endpoint_schema: Option<Vec<RouteObject>>,
+    // Files the user attached to the request, e.g. a wireframe or a PDF spec
+    #[serde(skip)]
+    pub attachments: Vec<InlineData>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
@@ -20,15 +25,68 @@ pub struct ProjectScope {
     pub is_external_urls_required: bool, // true if site needs to fetch data from third part providers
 }
 
+impl ResponseSchema for ProjectScope {
+    fn response_schema() -> serde_json::Value {
+        return serde_json::json!({
+            "type": "OBJECT",
+            "properties": {
+                "is_crud_required": bool::response_schema(),
+                "is_user_login_and_logout": bool::response_schema(),
+                "is_external_urls_required": bool::response_schema()
+            },
+            "required": ["is_crud_required", "is_user_login_and_logout", "is_external_urls_required"]
+        });
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct RouteObject {
     pub route: String,
     pub is_route_dynamic: String,
     pub method: String,
+    // Free-form JSON, null when the model did not describe it
+    #[serde(default, deserialize_with = "json_from_text")]
     pub request_body: serde_json::Value,
+    #[serde(default, deserialize_with = "json_from_text")]
     pub response: serde_json::Value,
 }
 
+impl ResponseSchema for RouteObject {
+    // Gemini schemas can not express free-form JSON objects, an OBJECT needs its properties. So the
+    // bodies are asked for as JSON text, which is parsed again when the routes are decoded.
+    fn response_schema() -> serde_json::Value {
+        let json_text: serde_json::Value = serde_json::json!({
+            "type": "STRING",
+            "description": "The JSON of the body, \"None\" when there is no body"
+        });
+        return serde_json::json!({
+            "type": "OBJECT",
+            "properties": {
+                "route": String::response_schema(),
+                "is_route_dynamic": String::response_schema(),
+                "method": String::response_schema(),
+                "request_body": json_text,
+                "response": json_text
+            },
+            "required": ["route", "is_route_dynamic", "method", "request_body", "response"]
+        });
+    }
+}
+
+// A body given as JSON text (structured output) is parsed, backends without a schema answer with
+// the JSON itself. Text which is no JSON, like "None", is kept as it is.
+fn json_from_text<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<serde_json::Value, D::Error> {
+    let value: serde_json::Value = serde_json::Value::deserialize(deserializer)?;
+    return Ok(match value {
+        serde_json::Value::String(text) => {
+            serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text))
+        }
+        value => value,
+    });
+}
+
 #[async_trait]
 pub trait SpecialFunctions: Debug + Send + Sync {
     // Used so that manager  van get attributes from agents
@@ -40,3 +98,30 @@ pub trait SpecialFunctions: Debug + Send + Sync {
         factsheet: &mut FactSheet,
     ) -> Result<(), Box<dyn std::error::Error>>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_object_decodes_structured_output() {
+        let schema: serde_json::Value = RouteObject::response_schema();
+        assert_eq!(schema["properties"]["request_body"]["type"], "STRING");
+
+        // Structured output holds the bodies as JSON text, JSON mode without a schema as JSON
+        let routes: Vec<RouteObject> = serde_json::from_str(
+            r#"[
+                { "route": "/todos", "is_route_dynamic": "false", "method": "get",
+                  "request_body": "None", "response": "[{\"id\": \"number\"}]" },
+                { "route": "/todos", "is_route_dynamic": "false", "method": "post",
+                  "request_body": { "title": "string" }, "response": { "id": "number" } },
+                { "route": "/health", "is_route_dynamic": "false", "method": "get" }
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(routes[0].request_body, "None");
+        assert_eq!(routes[0].response[0]["id"], "number");
+        assert_eq!(routes[1].request_body["title"], "string");
+        assert_eq!(routes[2].response, serde_json::Value::Null);
+    }
+}
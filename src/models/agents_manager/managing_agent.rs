@@ -1,49 +1,69 @@
-use crate::ai_functions::ai_func_managing::convert_user_input_to_goal;
+use crate::ai_functions::prompts::{CONVERT_USER_INPUT_TO_GOAL, Prompt, PromptRegistry};
+use crate::apis::llm_error::LlmError;
+use crate::apis::llm_provider::LlmProvider;
+use crate::helpers::command_line::PrintCommand;
 use crate::helpers::general::ai_task_request;
 use crate::models::agent_basic::basic_agent::{AgentState, BasicAgent};
+use crate::models::agent_basic::memory::{Memory, MemoryConfig};
 use crate::models::agents::agent_architect::AgentSolutionArchitect;
 use crate::models::agents::agent_backend::AgentBackendDeveloper;
 use crate::models::agents::agent_traits::{FactSheet, SpecialFunctions};
-use crate::models::general::llm::Message;
+use crate::models::general::llm::InlineData;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct ManagingAgent {
     attributes: BasicAgent,
     fact_sheet: FactSheet,
     agents: Vec<Box<dyn SpecialFunctions>>,
+    llm: Arc<dyn LlmProvider>,
+    // The prompt templates of the AI functions, shared with the agents
+    prompts: Arc<PromptRegistry>,
+    // Conversation history settings for the agents
+    memory_config: MemoryConfig,
 }
 
 impl ManagingAgent {
-    pub async fn new(user_request: String) -> Result<Self, Box<dyn std::error::Error>> {
+    // The attachments of the user go along with the request to the agents which shape the project
+    pub async fn new(
+        user_request: String,
+        attachments: Vec<InlineData>,
+        llm: Arc<dyn LlmProvider>,
+        prompts: Arc<PromptRegistry>,
+        memory_config: MemoryConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         let attributes: BasicAgent = BasicAgent {
             objective: "Manages agents who are building a excelent website for the user."
                 .to_string(),
             position: "Project Manager".to_string(),
             state: AgentState::Discovery,
-            memory: vec![],
+            memory: Memory::default(),
         };
 
-        let project_description: String = ai_task_request(
-            user_request,
-            &attributes.position,
-            get_function_string!(convert_user_input_to_goal),
-            convert_user_input_to_goal,
-        )
-        .await;
+        let prompt: Prompt = prompts.render(
+            CONVERT_USER_INPUT_TO_GOAL,
+            &[("user_request", &user_request)],
+        );
+        let project_description: String =
+            ai_task_request(llm.as_ref(), &prompt, &attachments, &attributes.position).await?;
         let agents: Vec<Box<dyn SpecialFunctions>> = Vec::new();
 
         let fact_sheet: FactSheet = FactSheet {
-            project_description: project_description,
+            project_description,
             project_scope: None,
             external_urls: None,
             backend_code: None,
             api_endpoint_schema: None,
+            attachments,
         };
 
         return Ok(Self {
             attributes,
             fact_sheet,
             agents,
+            llm,
+            prompts,
+            memory_config,
         });
     }
 
@@ -52,8 +72,15 @@ impl ManagingAgent {
     }
 
     fn create_agents(&mut self) {
-        self.add_agent(Box::new(AgentSolutionArchitect::new()));
-        self.add_agent(Box::new(AgentBackendDeveloper::new()));
+        self.add_agent(Box::new(AgentSolutionArchitect::new(
+            Arc::clone(&self.llm),
+            Arc::clone(&self.prompts),
+        )));
+        self.add_agent(Box::new(AgentBackendDeveloper::new(
+            Arc::clone(&self.llm),
+            Arc::clone(&self.prompts),
+            &self.memory_config,
+        )));
         // TODO: Add more agents
     }
 
@@ -64,6 +91,19 @@ impl ManagingAgent {
                 agent.execute(&mut self.fact_sheet).await;
             match result_agent {
                 Ok(_) => (),
+                // Running out of budget ends the project, not the program
+                Err(e)
+                    if matches!(
+                        e.downcast_ref::<LlmError>(),
+                        Some(LlmError::BudgetExceeded(_))
+                    ) =>
+                {
+                    PrintCommand::Issue.print_agent_message(
+                        &self.attributes.position,
+                        &format!("Stopping the project: {}", e),
+                    );
+                    break;
+                }
                 Err(e) => panic!(
                     "Error detected during executing agent {}: {}",
                     agent.get_attributes_from_agent().position,
@@ -74,25 +114,181 @@ impl ManagingAgent {
             let agent_info = agent.get_attributes_from_agent();
             dbg!(agent_info);
         }
+
+        // Report the tokens and estimated cost of the run
+        if let Some(ledger) = self.llm.usage_ledger() {
+            println!("\nLLM usage of this run:\n{}", ledger.report());
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use core::borrow;
-
     use super::*;
+    use crate::apis::budget::{Budget, BudgetProvider};
+    use crate::apis::call_request::GeminiClient;
+    use crate::apis::retry::{RetryPolicy, RetryProvider};
+    use crate::apis::usage::{MeteredProvider, UsageLedger, default_pricing};
+    use crate::test_support::cassettes::cassette_provider;
+    use crate::test_support::mock_gemini::{MockGemini, MockReply};
 
     #[tokio::test]
     async fn tests_managing_agent() {
         let user_input = "Ik wil graag een full-stack webserver die todo kaarten bij houd. Ook moet ik dit per gebruiker kunnen doen. Ik wil graag het weer op elk kaartje zichtbaar hebben!".to_string();
-        let mut agent: ManagingAgent = ManagingAgent::new(user_input)
-            .await
-            .expect("Failed to create Project Manager!");
+        let mut agent: ManagingAgent = ManagingAgent::new(
+            user_input,
+            vec![],
+            cassette_provider("managing_agent"),
+            Arc::new(PromptRegistry::defaults()),
+            MemoryConfig::default(),
+        )
+        .await
+        .expect("Failed to create Project Manager!");
 
         agent.execute_project().await;
         dbg!(&agent.fact_sheet);
         assert!(agent.fact_sheet.project_scope.is_some());
         assert!(agent.fact_sheet.external_urls.is_some());
     }
+
+    #[tokio::test]
+    async fn tests_managing_agent_against_mock_gemini() {
+        let mock: MockGemini = MockGemini::start().await;
+        let site_url: String = format!("{}/api/v1/weather", mock.base_url());
+        mock.respond(
+            "convert_user_input_to_goal",
+            MockReply::Text("build a website that tracks todo cards per user".to_string()),
+        )
+        // Malformed JSON first, the architect has to ask for a repair
+        .respond(
+            "print_project_scope",
+            MockReply::Text("{ is_crud_required: yes }".to_string()),
+        )
+        .respond(
+            "print_project_scope",
+            MockReply::json(serde_json::json!({
+                "is_crud_required": true,
+                "is_user_login_and_logout": true,
+                "is_external_urls_required": true
+            })),
+        )
+        .respond(
+            "print_site_urls",
+            MockReply::RateLimited {
+                retry_after: Some(0),
+            },
+        )
+        .respond(
+            "print_site_urls",
+            MockReply::json(serde_json::json!([site_url])),
+        );
+
+        let ledger: Arc<UsageLedger> = Arc::new(UsageLedger::new(default_pricing()));
+        let llm: Arc<dyn LlmProvider> = Arc::new(MeteredProvider::new(
+            Arc::new(RetryProvider::new(
+                Arc::new(GeminiClient::new(&mock.config()).unwrap()),
+                RetryPolicy {
+                    base_delay_ms: 0,
+                    ..Default::default()
+                },
+            )),
+            Arc::clone(&ledger),
+        ));
+        let mut agent: ManagingAgent = ManagingAgent::new(
+            "I want a todo app with weather".to_string(),
+            vec![InlineData {
+                mime_type: "image/png".to_string(),
+                data: "iVBORw==".to_string(),
+            }],
+            llm,
+            Arc::new(PromptRegistry::defaults()),
+            MemoryConfig::default(),
+        )
+        .await
+        .expect("Failed to create Project Manager!");
+        agent.execute_project().await;
+
+        assert_eq!(
+            agent.fact_sheet.project_description,
+            "build a website that tracks todo cards per user"
+        );
+        assert!(
+            agent
+                .fact_sheet
+                .project_scope
+                .unwrap()
+                .is_external_urls_required
+        );
+        assert_eq!(agent.fact_sheet.external_urls, Some(vec![site_url]));
+        assert_eq!(mock.calls_for("print_project_scope"), 2);
+        assert_eq!(mock.calls_for("print_site_urls"), 2);
+        // The wireframe goes along with the goal and the scope, not with the code
+        let wireframes: usize = mock
+            .requests()
+            .iter()
+            .filter(|request| request.body.contains("\"inline_data\""))
+            .count();
+        assert_eq!(wireframes, 5);
+
+        // Only successful calls are metered, the repair of the scope counts as a call
+        let calls: Vec<(String, u32)> = ledger
+            .totals_per_call_site()
+            .into_iter()
+            .map(|(context, totals)| (context.function, totals.calls))
+            .collect();
+        assert_eq!(
+            calls,
+            vec![
+                ("convert_user_input_to_goal".to_string(), 1),
+                ("print_project_scope".to_string(), 2),
+                ("print_site_urls".to_string(), 1)
+            ]
+        );
+        assert!(ledger.totals().cost > 0.0);
+    }
+
+    #[tokio::test]
+    async fn tests_managing_agent_stops_at_budget() {
+        let mock: MockGemini = MockGemini::start().await;
+        mock.respond(
+            "convert_user_input_to_goal",
+            MockReply::Text("build a website that shows crypto prices".to_string()),
+        )
+        .respond(
+            "print_project_scope",
+            MockReply::json(serde_json::json!({
+                "is_crud_required": false,
+                "is_user_login_and_logout": false,
+                "is_external_urls_required": true
+            })),
+        )
+        .respond("print_site_urls", MockReply::json(serde_json::json!([])));
+
+        let ledger: Arc<UsageLedger> = Arc::new(UsageLedger::new(default_pricing()));
+        let metered: Arc<dyn LlmProvider> = Arc::new(MeteredProvider::new(
+            Arc::new(GeminiClient::new(&mock.config()).unwrap()),
+            Arc::clone(&ledger),
+        ));
+        let budget: Budget = Budget {
+            max_calls: Some(2),
+            ..Default::default()
+        };
+        let llm: Arc<dyn LlmProvider> = Arc::new(BudgetProvider::new(metered, ledger, budget));
+
+        let mut agent: ManagingAgent = ManagingAgent::new(
+            "Crypto prices please".to_string(),
+            vec![],
+            llm,
+            Arc::new(PromptRegistry::defaults()),
+            MemoryConfig::default(),
+        )
+        .await
+        .expect("Failed to create Project Manager!");
+        agent.execute_project().await;
+
+        // The goal and the scope fit in the budget, the urls do not
+        assert!(agent.fact_sheet.project_scope.is_some());
+        assert_eq!(agent.fact_sheet.external_urls, None);
+        assert_eq!(mock.calls_for("print_site_urls"), 0);
+    }
 }
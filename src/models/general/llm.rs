@@ -1,40 +1,237 @@
 #![allow(dead_code)]
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-#[derive(Debug, Serialize, Clone)]
+pub const ROLE_USER: &str = "user";
+pub const ROLE_MODEL: &str = "model";
+
+// Request body of generateContent, streamGenerateContent and the cached contents
+#[derive(Debug, Serialize, Clone, Default)]
 pub struct Message {
+    // The conversation so far, alternating between the user and the model
     pub contents: Vec<MessagePart>,
+    // Instructions for the model which are not part of the conversation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<MessagePart>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub safety_settings: Vec<SafetySetting>,
+    // Local functions the model may call instead of answering
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub generation_config: Option<GenerationConfig>,
+    // Name of an uploaded prefix (cachedContents/...) which comes before the contents. The system
+    // instruction and tools are part of the prefix then.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cached_content: Option<String>,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Tool {
+    pub function_declarations: Vec<FunctionDeclaration>,
+}
+
+// Name, purpose and parameters (an OpenAPI schema, like response_schema) of a local function
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+}
+
+// The model asks for a local function to be called with these arguments
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub args: serde_json::Value,
+}
+
+// The result of a local function, sent back to the model
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FunctionResponse {
+    pub name: String,
+    pub response: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
 pub struct GenerationConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub candidate_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub max_output_tokens: Option<u32>,
+    // The answer stops before the first of these sequences
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop_sequences: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_mime_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_schema: Option<serde_json::Value>,
+}
+
+// Threshold for one harm category, e.g. HARM_CATEGORY_DANGEROUS_CONTENT with BLOCK_ONLY_HIGH
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct SafetySetting {
+    pub category: String,
+    pub threshold: String,
 }
 
 #[derive(Debug, Serialize, Clone)]
 pub struct MessagePart {
+    // "user" or "model", not set for the system instruction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
     pub parts: Vec<MessagePartText>,
 }
 
-#[derive(Debug, Serialize, Clone)]
+impl MessagePart {
+    pub fn user(text: String) -> Self {
+        return Self::with_role(ROLE_USER, text);
+    }
+
+    pub fn model(text: String) -> Self {
+        return Self::with_role(ROLE_MODEL, text);
+    }
+
+    // Content without a role, as used for the system instruction
+    pub fn text(text: String) -> Self {
+        return Self {
+            role: None,
+            parts: vec![MessagePartText {
+                text,
+                ..Default::default()
+            }],
+        };
+    }
+
+    // Add files after the text of the turn
+    pub fn attach(&mut self, attachments: &[InlineData]) {
+        self.parts
+            .extend(attachments.iter().map(|attachment| MessagePartText {
+                inline_data: Some(attachment.clone()),
+                ..Default::default()
+            }));
+    }
+
+    // Text of all parts, one per line
+    pub fn joined_text(&self) -> String {
+        return self
+            .parts
+            .iter()
+            .map(|part| part.text.as_str())
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<&str>>()
+            .join("\n");
+    }
+
+    // The role in chat completion terms, where the model is the "assistant"
+    pub fn chat_role(&self) -> &str {
+        return match self.role.as_deref() {
+            Some(ROLE_MODEL) => "assistant",
+            _ => "user",
+        };
+    }
+
+    fn with_role(role: &str, text: String) -> Self {
+        return Self {
+            role: Some(role.to_string()),
+            parts: vec![MessagePartText {
+                text,
+                ..Default::default()
+            }],
+        };
+    }
+}
+
+// One part of a turn: text, a file, a function call of the model or the response to it
+#[derive(Debug, Serialize, Clone, Default)]
 pub struct MessagePartText {
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inline_data: Option<InlineData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<FunctionCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_response: Option<FunctionResponse>,
+}
+
+impl MessagePartText {
+    // Size of the part in characters, for estimating tokens
+    pub fn characters(&self) -> usize {
+        let function_call: usize = self.function_call.as_ref().map_or(0, |call| {
+            call.name.chars().count() + call.args.to_string().chars().count()
+        });
+        let function_response: usize = self.function_response.as_ref().map_or(0, |response| {
+            response.name.chars().count() + response.response.to_string().chars().count()
+        });
+        let inline_data: usize = self
+            .inline_data
+            .as_ref()
+            .map_or(0, |_| INLINE_DATA_TOKENS * 4);
+        return self.text.chars().count() + function_call + function_response + inline_data;
+    }
+}
+
+// Gemini counts an image, or a page of a document, as 258 tokens
+const INLINE_DATA_TOKENS: usize = 258;
+
+// A file sent along with the prompt, e.g. a wireframe screenshot or a PDF spec
+#[derive(Serialize, Clone, PartialEq)]
+pub struct InlineData {
+    pub mime_type: String,
+    // The file contents, base64 encoded
+    pub data: String,
+}
+
+// The base64 data would flood debug prints of the fact sheet
+impl fmt::Debug for InlineData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InlineData")
+            .field("mime_type", &self.mime_type)
+            .field("data", &format!("{} base64 characters", self.data.len()))
+            .finish()
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct GeminiResponse {
+    #[serde(default)]
     pub candidates: Vec<ResponseContent>,
+    #[serde(rename = "promptFeedback")]
+    pub prompt_feedback: Option<PromptFeedback>, // Only present when the prompt is blocked
     #[serde(rename = "usageMetadata")]
     pub usage_metadata: Option<UsageMetadata>, // Optional field
     #[serde(rename = "modelVersion")]
     pub model_version: Option<String>, // Optional field
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct PromptFeedback {
+    #[serde(rename = "blockReason")]
+    pub block_reason: Option<String>,
+    #[serde(rename = "safetyRatings", default)]
+    pub safety_ratings: Vec<SafetyRating>,
+}
+
+// How likely a prompt or answer is harmful in one category
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct SafetyRating {
+    pub category: String,
+    pub probability: String, // NEGLIGIBLE, LOW, MEDIUM or HIGH
+    #[serde(default)]
+    pub blocked: bool,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct UsageMetadata {
     #[serde(rename = "promptTokenCount")]
@@ -58,14 +255,21 @@ pub struct TokenDetails {
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ResponseContent {
-    pub content: ResponseContentParts,
+    // Which of the alternative answers this is, streamed events may hold any of them
+    #[serde(default)]
+    pub index: u32,
+    #[serde(default)]
+    pub content: ResponseContentParts, // Missing when the candidate is blocked
     #[serde(rename = "finishReason")]
     pub finish_reason: Option<String>,
+    #[serde(rename = "safetyRatings", default)]
+    pub safety_ratings: Vec<SafetyRating>,
     pub avg_logprobs: Option<f64>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct ResponseContentParts {
+    #[serde(default)]
     pub parts: Vec<ResponseContentPartsText>,
     pub role: Option<String>,
 }
@@ -73,4 +277,18 @@ pub struct ResponseContentParts {
 #[derive(Debug, Deserialize, Clone)]
 pub struct ResponseContentPartsText {
     pub text: Option<String>,
+    #[serde(rename = "functionCall")]
+    pub function_call: Option<FunctionCall>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CountTokensResponse {
+    #[serde(rename = "totalTokens")]
+    pub total_tokens: u32,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CachedContentResponse {
+    // e.g. "cachedContents/a1b2c3"
+    pub name: String,
 }
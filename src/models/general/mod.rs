@@ -1 +1,4 @@
 pub mod llm;
+pub mod ollama;
+pub mod openai;
+pub mod response_schema;
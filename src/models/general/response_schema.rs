@@ -0,0 +1,37 @@
+use serde_json::{Value, json};
+
+// Types the model can be asked to produce directly as JSON (Gemini responseSchema)
+pub trait ResponseSchema {
+    fn response_schema() -> Value;
+}
+
+impl ResponseSchema for String {
+    fn response_schema() -> Value {
+        return json!({ "type": "STRING" });
+    }
+}
+
+impl ResponseSchema for bool {
+    fn response_schema() -> Value {
+        return json!({ "type": "BOOLEAN" });
+    }
+}
+
+impl<T: ResponseSchema> ResponseSchema for Vec<T> {
+    fn response_schema() -> Value {
+        return json!({ "type": "ARRAY", "items": T::response_schema() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vec_response_schema() {
+        assert_eq!(
+            Vec::<String>::response_schema(),
+            json!({ "type": "ARRAY", "items": { "type": "STRING" } })
+        );
+    }
+}
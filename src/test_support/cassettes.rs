@@ -0,0 +1,28 @@
+use crate::apis::cassette::{RecordingProvider, ReplayProvider};
+use crate::apis::llm_provider::{LlmProvider, build_provider};
+use crate::helpers::config::AppConfig;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Provider for tests backed by the committed tests/cassettes/<name>.json. LLM_CASSETTE_MODE selects:
+//   replay (default) - never touch the network, a missing answer fails with MissingRecording
+//   once             - replay recorded answers, record missing ones with the configured backend
+//   record           - call the backend for every request and overwrite the recordings
+pub fn cassette_provider(name: &str) -> Arc<dyn LlmProvider> {
+    let path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("cassettes")
+        .join(format!("{}.json", name));
+    let mode: String = std::env::var("LLM_CASSETTE_MODE").unwrap_or("replay".to_string());
+
+    return match mode.as_str() {
+        "replay" => Arc::new(ReplayProvider::new(path)),
+        "record" => Arc::new(RecordingProvider::new(live_provider(), path)),
+        "once" => Arc::new(RecordingProvider::once(live_provider(), path)),
+        _ => panic!("Unknown LLM_CASSETTE_MODE: {}", mode),
+    };
+}
+
+fn live_provider() -> Arc<dyn LlmProvider> {
+    return build_provider(&AppConfig::load()).expect("Failed to build the LLM provider");
+}
@@ -0,0 +1,262 @@
+#![allow(unused)]
+use crate::helpers::config::GeminiConfig;
+use crate::helpers::secret::ApiKey;
+use crate::test_support::stub_server::{RecordedRequest, StubResponse, StubServer};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+// A scripted answer of the mock Gemini server
+#[derive(Debug, Clone)]
+pub enum MockReply {
+    // A normal answer with this text
+    Text(String),
+    // An answer cut off at the output token limit (finishReason MAX_TOKENS)
+    Truncated(String),
+    // The model calls a local function instead of answering
+    FunctionCall {
+        name: String,
+        args: serde_json::Value,
+    },
+    // 429 Too Many Requests, optionally with a Retry-After header (seconds)
+    RateLimited {
+        retry_after: Option<u64>,
+    },
+    // A 200 answer without any candidates
+    EmptyCandidates,
+    // The prompt is blocked with this reason
+    Blocked(String),
+    // Any other status code with an error body
+    Status(u16),
+}
+
+impl MockReply {
+    pub fn json(value: serde_json::Value) -> Self {
+        return Self::Text(value.to_string());
+    }
+}
+
+type Scripts = Arc<Mutex<Vec<(String, VecDeque<MockReply>)>>>;
+// Uploaded prefixes, cachedContents/mock-<index>
+type CachedContents = Arc<Mutex<Vec<serde_json::Value>>>;
+
+// In-process server speaking the Gemini generateContent, streamGenerateContent, countTokens and
+// cachedContents wire format. Answers are scripted per AI function, recognised by its name in the
+// prompt. GET requests are answered with 200 so the mock can also stand in for external urls.
+pub struct MockGemini {
+    server: StubServer,
+    scripts: Scripts,
+}
+
+impl MockGemini {
+    pub async fn start() -> Self {
+        let scripts: Scripts = Arc::new(Mutex::new(vec![]));
+        let server_scripts: Scripts = Arc::clone(&scripts);
+        let cached_contents: CachedContents = Arc::new(Mutex::new(vec![]));
+        let server: StubServer = StubServer::start(move |request| {
+            handle_request(&server_scripts, &cached_contents, request)
+        })
+        .await;
+        return Self { server, scripts };
+    }
+
+    // Queue a reply for prompts of the AI function. The last reply of a function keeps repeating.
+    pub fn respond(&self, ai_function: &str, reply: MockReply) -> &Self {
+        let mut scripts = self.scripts.lock().unwrap();
+        match scripts.iter_mut().find(|(name, _)| name == ai_function) {
+            Some((_, replies)) => replies.push_back(reply),
+            None => scripts.push((ai_function.to_string(), VecDeque::from([reply]))),
+        }
+        return self;
+    }
+
+    pub fn base_url(&self) -> &str {
+        return &self.server.base_url;
+    }
+
+    // Config pointing the Gemini provider at this server
+    pub fn config(&self) -> GeminiConfig {
+        return GeminiConfig {
+            base_url: self.server.base_url.clone(),
+            api_key: Some(ApiKey::new("mock-api-key")),
+            ..Default::default()
+        };
+    }
+
+    // Every request received so far
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        return self.server.requests();
+    }
+
+    // Model requests (generate and stream) received for the AI function
+    pub fn calls_for(&self, ai_function: &str) -> usize {
+        return self
+            .requests()
+            .iter()
+            .filter(|request| request.method == "POST" && request.path.starts_with("/models/"))
+            .filter(|request| !request.path.contains(":countTokens"))
+            .filter(|request| prompt_text(request).contains(ai_function))
+            .count();
+    }
+}
+
+fn handle_request(
+    scripts: &Scripts,
+    cached_contents: &CachedContents,
+    request: &RecordedRequest,
+) -> StubResponse {
+    if request.method == "GET" {
+        return StubResponse::json(200, serde_json::json!({ "status": "ok" }));
+    }
+    // Like Gemini, only the header is accepted, a key in the url is not
+    if request.header("x-goog-api-key") != Some("mock-api-key") {
+        return error_response(403, "Method doesn't allow unregistered callers");
+    }
+
+    let body: serde_json::Value = request.json();
+    if request.path == "/cachedContents" {
+        let mut cached_contents = cached_contents.lock().unwrap();
+        cached_contents.push(body);
+        let name: String = format!("cachedContents/mock-{}", cached_contents.len() - 1);
+        return StubResponse::json(200, serde_json::json!({ "name": name }));
+    }
+    // countTokens wraps the request
+    let body: &serde_json::Value = body.get("generateContentRequest").unwrap_or(&body);
+    if let Some(name) = body["cached_content"].as_str() {
+        let known: bool = name
+            .strip_prefix("cachedContents/mock-")
+            .and_then(|index| index.parse::<usize>().ok())
+            .is_some_and(|index| index < cached_contents.lock().unwrap().len());
+        if !known {
+            return error_response(404, "CachedContent not found");
+        }
+        // Like Gemini, the instruction and tools have to be part of the cached content
+        if body.get("system_instruction").is_some() || body.get("tools").is_some() {
+            return error_response(
+                400,
+                "CachedContent can not be used with GenerateContent request setting system_instruction, tools or tool_config",
+            );
+        }
+    }
+
+    let prompt: String = prompt_text(request);
+    if request.path.contains(":countTokens") {
+        return StubResponse::json(
+            200,
+            serde_json::json!({ "totalTokens": prompt.chars().count().div_ceil(4) }),
+        );
+    }
+
+    let reply: Option<MockReply> = {
+        let mut scripts = scripts.lock().unwrap();
+        scripts
+            .iter_mut()
+            .find(|(name, _)| prompt.contains(name.as_str()))
+            .and_then(|(_, replies)| match replies.len() {
+                1 => replies.front().cloned(),
+                _ => replies.pop_front(),
+            })
+    };
+    let Some(reply) = reply else {
+        return error_response(404, "No mock reply scripted for this prompt");
+    };
+
+    let body: serde_json::Value = match reply {
+        MockReply::Text(text) => answer(&prompt, &text, "STOP"),
+        MockReply::Truncated(text) => answer(&prompt, &text, "MAX_TOKENS"),
+        MockReply::FunctionCall { name, args } => serde_json::json!({
+            "candidates": [{
+                "content": {
+                    "parts": [{ "functionCall": { "name": name, "args": args } }],
+                    "role": "model"
+                },
+                "finishReason": "STOP"
+            }]
+        }),
+        MockReply::EmptyCandidates => serde_json::json!({ "candidates": [] }),
+        MockReply::Blocked(reason) => {
+            serde_json::json!({ "promptFeedback": { "blockReason": reason } })
+        }
+        MockReply::RateLimited { retry_after } => {
+            let response: StubResponse = error_response(429, "Resource has been exhausted");
+            return match retry_after {
+                Some(seconds) => response.with_header("Retry-After", &seconds.to_string()),
+                None => response,
+            };
+        }
+        MockReply::Status(status) => return error_response(status, "Mock failure"),
+    };
+
+    if request.path.contains(":streamGenerateContent") {
+        return sse_response(&body);
+    }
+    return StubResponse::json(200, body);
+}
+
+fn answer(prompt: &str, text: &str, finish_reason: &str) -> serde_json::Value {
+    return serde_json::json!({
+        "candidates": [{
+            "content": { "parts": [{ "text": text }], "role": "model" },
+            "finishReason": finish_reason
+        }],
+        "usageMetadata": {
+            "promptTokenCount": prompt.chars().count().div_ceil(4),
+            "candidatesTokenCount": text.chars().count().div_ceil(4),
+            "totalTokenCount": (prompt.chars().count() + text.chars().count()).div_ceil(4)
+        },
+        "modelVersion": "mock"
+    });
+}
+
+// Text of all parts of the request contents
+fn prompt_text(request: &RecordedRequest) -> String {
+    let body: serde_json::Value = serde_json::from_str(&request.body).unwrap_or_default();
+    // countTokens wraps the request
+    let body: &serde_json::Value = match body.get("generateContentRequest") {
+        Some(request) => request,
+        None => &body,
+    };
+    let mut text: String = String::new();
+    for content in body["contents"].as_array().into_iter().flatten() {
+        for part in content["parts"].as_array().into_iter().flatten() {
+            text.push_str(part["text"].as_str().unwrap_or(""));
+        }
+    }
+    return text;
+}
+
+fn error_response(status: u16, message: &str) -> StubResponse {
+    return StubResponse::json(
+        status,
+        serde_json::json!({ "error": { "code": status, "message": message } }),
+    );
+}
+
+// Stream the answer as server sent events: the text split in two chunks, then the usage
+fn sse_response(body: &serde_json::Value) -> StubResponse {
+    let mut events: Vec<serde_json::Value> = vec![];
+    match body["candidates"][0]["content"]["parts"][0]["text"].as_str() {
+        Some(text) => {
+            let middle: usize = text
+                .char_indices()
+                .nth(text.chars().count() / 2)
+                .map_or(0, |(i, _)| i);
+            for chunk in [&text[..middle], &text[middle..]] {
+                events.push(serde_json::json!({
+                    "candidates": [{ "content": { "parts": [{ "text": chunk }], "role": "model" } }]
+                }));
+            }
+            let mut last: serde_json::Value = body.clone();
+            last["candidates"][0]["content"]["parts"] = serde_json::json!([]);
+            events.push(last);
+        }
+        None => events.push(body.clone()),
+    }
+
+    let mut response: StubResponse = StubResponse::json(200, serde_json::Value::Null);
+    response.headers = vec![("Content-Type".to_string(), "text/event-stream".to_string())];
+    response.body = events
+        .iter()
+        .map(|event| format!("data: {}\r\n\r\n", event))
+        .collect();
+    return response;
+}
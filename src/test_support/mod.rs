@@ -0,0 +1,4 @@
+pub mod cassettes;
+pub mod mock_gemini;
+pub mod scripted_provider;
+pub mod stub_server;
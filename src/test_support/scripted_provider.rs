@@ -0,0 +1,75 @@
+#![allow(unused)]
+use crate::apis::llm_error::LlmError;
+use crate::apis::llm_provider::{
+    CallContext, FinishReason, LlmProvider, LlmResponse, estimate_tokens, estimate_usage,
+};
+use crate::models::general::llm::Message;
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+// Provider answering from a queue of prepared results, recording every message it receives.
+// Answers come from the model "scripted" with an estimated usage.
+#[derive(Debug, Default)]
+pub struct ScriptedProvider {
+    responses: Mutex<VecDeque<Result<String, LlmError>>>,
+    received: Mutex<Vec<Message>>,
+    contexts: Mutex<Vec<CallContext>>,
+}
+
+impl ScriptedProvider {
+    pub fn new(responses: Vec<Result<String, LlmError>>) -> Self {
+        return Self {
+            responses: Mutex::new(responses.into()),
+            received: Mutex::new(vec![]),
+            contexts: Mutex::new(vec![]),
+        };
+    }
+
+    // Messages received so far
+    pub fn received(&self) -> Vec<Message> {
+        return self.received.lock().unwrap().clone();
+    }
+
+    // Call contexts received so far
+    pub fn contexts(&self) -> Vec<CallContext> {
+        return self.contexts.lock().unwrap().clone();
+    }
+
+    pub fn calls(&self) -> usize {
+        return self.received.lock().unwrap().len();
+    }
+}
+
+#[async_trait]
+impl LlmProvider for ScriptedProvider {
+    async fn generate(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<LlmResponse, LlmError> {
+        self.received.lock().unwrap().push(message.clone());
+        self.contexts.lock().unwrap().push(context.clone());
+        let text: String = self
+            .responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .expect("ScriptedProvider ran out of responses")?;
+        return Ok(LlmResponse {
+            usage: estimate_usage(message, &text),
+            model: "scripted".to_string(),
+            text,
+            finish_reason: FinishReason::Stop,
+            function_calls: vec![],
+        });
+    }
+
+    async fn count_tokens(
+        &self,
+        message: &Message,
+        context: &CallContext,
+    ) -> Result<u32, LlmError> {
+        return Ok(estimate_tokens(message));
+    }
+}